@@ -0,0 +1,94 @@
+use super::SdtHeader;
+
+#[repr(C, packed)]
+pub struct Fadt {
+    pub header: SdtHeader,
+    pub firmware_ctrl: u32,
+    pub dsdt: u32,
+    pub reserved0: u8,
+    pub preferred_pm_profile: u8,
+    pub sci_interrupt: u16,
+    pub smi_command_port: u32,
+    pub acpi_enable: u8,
+    pub acpi_disable: u8,
+    pub s4bios_request: u8,
+    pub pstate_control: u8,
+    pub pm1a_event_block: u32,
+    pub pm1b_event_block: u32,
+    pub pm1a_control_block: u32,
+    pub pm1b_control_block: u32,
+    pub pm2_control_block: u32,
+    pub pm_timer_block: u32,
+    pub gpe0_block: u32,
+    pub gpe1_block: u32,
+    pub pm1_event_length: u8,
+    pub pm1_control_length: u8,
+    pub pm2_control_length: u8,
+    pub pm_timer_length: u8,
+    pub gpe0_length: u8,
+    pub gpe1_length: u8,
+    pub gpe1_base: u8,
+    pub cstate_control: u8,
+    pub worst_c2_latency: u16,
+    pub worst_c3_latency: u16,
+    pub flush_size: u16,
+    pub flush_stride: u16,
+    pub duty_offset: u8,
+    pub duty_width: u8,
+    pub day_alarm: u8,
+    pub month_alarm: u8,
+    pub century: u8,
+    pub iapc_boot_arch: u16,
+    pub reserved1: u8,
+    pub flags: u32,
+}
+
+impl Fadt {
+    pub const SIGNATURE: &'static [u8; 4] = b"FACP";
+
+    pub fn sci_interrupt(&self) -> u16 {
+        self.sci_interrupt
+    }
+
+    pub fn pm_timer_port(&self) -> Option<u16> {
+        match self.pm_timer_block {
+            0 => None,
+            port => Some(port as u16),
+        }
+    }
+
+    pub fn century_register(&self) -> Option<u8> {
+        match self.century {
+            0 => None,
+            reg => Some(reg),
+        }
+    }
+
+    // IA-PC boot architecture flags only exist since ACPI 2.0
+    pub fn has_8042(&self) -> bool {
+        self.header.revision < 2 || self.iapc_boot_arch & (1 << 1) != 0
+    }
+
+    pub fn has_cmos_rtc(&self) -> bool {
+        self.header.revision < 2 || self.iapc_boot_arch & (1 << 5) == 0
+    }
+}
+
+impl core::fmt::Debug for Fadt {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let dsdt = self.dsdt;
+        let flags = self.flags;
+
+        write!(
+            f,
+            "Fadt {{ dsdt: 0x{:08X}, sci_interrupt: {}, pm_timer_port: {:?}, century_register: {:?}, has_8042: {}, has_cmos_rtc: {}, flags: 0x{:08X} }}",
+            dsdt,
+            self.sci_interrupt(),
+            self.pm_timer_port(),
+            self.century_register(),
+            self.has_8042(),
+            self.has_cmos_rtc(),
+            flags
+        )
+    }
+}
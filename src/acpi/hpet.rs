@@ -0,0 +1,54 @@
+use super::{GenericAddress, SdtHeader};
+
+#[repr(C, packed)]
+pub struct Hpet {
+    pub header: SdtHeader,
+    pub event_timer_block_id: u32,
+    pub base_address: GenericAddress,
+    pub hpet_number: u8,
+    pub minimum_tick: u16,
+    pub page_protection: u8,
+}
+
+impl Hpet {
+    pub const SIGNATURE: &'static [u8; 4] = b"HPET";
+
+    pub fn base_address(&self) -> u64 {
+        self.base_address.address()
+    }
+
+    pub fn comparator_count(&self) -> u8 {
+        (((self.event_timer_block_id >> 8) & 0x1F) + 1) as u8
+    }
+
+    pub fn counter_is_64bit(&self) -> bool {
+        self.event_timer_block_id & (1 << 13) != 0
+    }
+
+    pub fn legacy_replacement_capable(&self) -> bool {
+        self.event_timer_block_id & (1 << 15) != 0
+    }
+
+    pub fn vendor_id(&self) -> u16 {
+        (self.event_timer_block_id >> 16) as u16
+    }
+
+    pub fn minimum_tick(&self) -> u16 {
+        self.minimum_tick
+    }
+}
+
+impl core::fmt::Debug for Hpet {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "Hpet {{ base_address: 0x{:08X}, comparators: {}, 64bit: {}, legacy_replacement: {}, vendor: 0x{:04X}, minimum_tick: {} }}",
+            self.base_address(),
+            self.comparator_count(),
+            self.counter_is_64bit(),
+            self.legacy_replacement_capable(),
+            self.vendor_id(),
+            self.minimum_tick()
+        )
+    }
+}
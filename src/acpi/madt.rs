@@ -0,0 +1,123 @@
+use super::SdtHeader;
+
+#[repr(C, packed)]
+pub struct Madt {
+    pub header: SdtHeader,
+    pub local_apic_address: u32,
+    pub flags: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum MadtEntry {
+    LocalApic {
+        processor_id: u8,
+        apic_id: u8,
+        flags: u32,
+    },
+    IoApic {
+        id: u8,
+        address: u32,
+        gsi_base: u32,
+    },
+    InterruptSourceOverride {
+        bus: u8,
+        source: u8,
+        gsi: u32,
+        flags: u16,
+    },
+    LocalApicNmi {
+        processor_id: u8,
+        flags: u16,
+        lint: u8,
+    },
+    LocalApicAddressOverride {
+        address: u64,
+    },
+    LocalX2Apic {
+        x2apic_id: u32,
+        flags: u32,
+        processor_uid: u32,
+    },
+    Unknown {
+        typ: u8,
+        length: u8,
+    },
+}
+
+impl Madt {
+    pub const SIGNATURE: &'static [u8; 4] = b"APIC";
+
+    pub fn entries(&self) -> MadtEntries {
+        let start = self as *const _ as usize + core::mem::size_of::<Madt>();
+        let end = self as *const _ as usize + self.header.length();
+
+        MadtEntries {
+            current: start,
+            end,
+        }
+    }
+}
+
+pub struct MadtEntries {
+    current: usize,
+    end: usize,
+}
+
+impl Iterator for MadtEntries {
+    type Item = MadtEntry;
+
+    fn next(&mut self) -> Option<MadtEntry> {
+        if self.current + 2 > self.end {
+            return None;
+        }
+
+        let base = self.current as *const u8;
+        let (typ, length) = unsafe { (*base, *base.add(1)) };
+        if length < 2 {
+            return None;
+        }
+
+        self.current += length as usize;
+
+        let u8_at = |offset: usize| unsafe { *base.add(offset) };
+        let u16_at =
+            |offset: usize| unsafe { core::ptr::read_unaligned(base.add(offset) as *const u16) };
+        let u32_at =
+            |offset: usize| unsafe { core::ptr::read_unaligned(base.add(offset) as *const u32) };
+        let u64_at =
+            |offset: usize| unsafe { core::ptr::read_unaligned(base.add(offset) as *const u64) };
+
+        let entry = match typ {
+            0 => MadtEntry::LocalApic {
+                processor_id: u8_at(2),
+                apic_id: u8_at(3),
+                flags: u32_at(4),
+            },
+            1 => MadtEntry::IoApic {
+                id: u8_at(2),
+                address: u32_at(4),
+                gsi_base: u32_at(8),
+            },
+            2 => MadtEntry::InterruptSourceOverride {
+                bus: u8_at(2),
+                source: u8_at(3),
+                gsi: u32_at(4),
+                flags: u16_at(8),
+            },
+            4 => MadtEntry::LocalApicNmi {
+                processor_id: u8_at(2),
+                flags: u16_at(3),
+                lint: u8_at(5),
+            },
+            5 => MadtEntry::LocalApicAddressOverride { address: u64_at(4) },
+            9 => MadtEntry::LocalX2Apic {
+                x2apic_id: u32_at(4),
+                flags: u32_at(8),
+                processor_uid: u32_at(12),
+            },
+            typ => MadtEntry::Unknown { typ, length },
+        };
+
+        Some(entry)
+    }
+}
@@ -0,0 +1,46 @@
+use super::SdtHeader;
+
+#[repr(C, packed)]
+pub struct Mcfg {
+    pub header: SdtHeader,
+    pub reserved: u64,
+}
+
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+pub struct McfgEntry {
+    pub base_address: u64,
+    pub segment_group: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+    pub reserved: u32,
+}
+
+impl Mcfg {
+    pub const SIGNATURE: &'static [u8; 4] = b"MCFG";
+
+    pub fn entries(&self) -> impl Iterator<Item = McfgEntry> {
+        let start = self as *const _ as usize + core::mem::size_of::<Mcfg>();
+        let count = (self.header.length() - core::mem::size_of::<Mcfg>())
+            / core::mem::size_of::<McfgEntry>();
+
+        (0..count).map(move |i| unsafe {
+            core::ptr::read_unaligned(
+                (start + i * core::mem::size_of::<McfgEntry>()) as *const McfgEntry,
+            )
+        })
+    }
+}
+
+impl core::fmt::Debug for McfgEntry {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let base_address = self.base_address;
+        let segment_group = self.segment_group;
+
+        write!(
+            f,
+            "McfgEntry {{ base_address: 0x{:08X}, segment_group: {}, buses: {}..={} }}",
+            base_address, segment_group, self.start_bus, self.end_bus
+        )
+    }
+}
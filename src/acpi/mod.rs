@@ -0,0 +1,257 @@
+mod fadt;
+mod hpet;
+mod madt;
+mod mcfg;
+mod rsdp;
+
+pub use fadt::*;
+pub use hpet::*;
+pub use madt::*;
+pub use mcfg::*;
+pub use rsdp::*;
+
+use alloc::vec::Vec;
+
+use multiboot2::BootInformation;
+
+use crate::mem::paging::EntryFlags;
+use crate::mem::{vmm, KERNEL_OFFSET};
+
+static mut ACPI: Option<Acpi> = None;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcpiError {
+    RsdpNotFound,
+    InvalidRsdpChecksum(RsdpSource),
+    InvalidRootTable,
+}
+
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+impl SdtHeader {
+    pub fn signature(&self) -> &str {
+        core::str::from_utf8(&self.signature).unwrap_or("????")
+    }
+
+    pub fn length(&self) -> usize {
+        self.length as usize
+    }
+
+    pub fn checksum_is_valid(&self) -> bool {
+        rsdp::checksum(self as *const _ as usize, self.length())
+    }
+}
+
+impl core::fmt::Debug for SdtHeader {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let length = self.length;
+        let revision = self.revision;
+
+        write!(
+            f,
+            "SdtHeader {{ signature: {}, length: {}, revision: {}, valid: {} }}",
+            self.signature(),
+            length,
+            revision,
+            self.checksum_is_valid()
+        )
+    }
+}
+
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+pub struct GenericAddress {
+    pub address_space: u8,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+impl GenericAddress {
+    pub const SPACE_SYSTEM_MEMORY: u8 = 0;
+
+    pub fn address(&self) -> u64 {
+        self.address
+    }
+}
+
+pub struct Acpi {
+    rsdp: &'static Rsdp,
+    source: RsdpSource,
+    root: &'static SdtHeader,
+    entry_size: usize,
+    // Physical addresses of the tables `map_tables` moved into the kernel's part of the address
+    // space and where they are now, the others are still reached through the identity mapping
+    mapped: Vec<(usize, &'static SdtHeader)>,
+}
+
+impl Acpi {
+    pub fn from_boot_info(boot_info: &BootInformation) -> Result<Acpi, AcpiError> {
+        let (rsdp, source) = Rsdp::locate(boot_info).ok_or(AcpiError::RsdpNotFound)?;
+        if !rsdp.checksum_is_valid() {
+            return Err(AcpiError::InvalidRsdpChecksum(source));
+        }
+
+        let (root, entry_size) = match rsdp.xsdt_address() {
+            Some(address) => (address, 8),
+            None => (rsdp.rsdt_address(), 4),
+        };
+
+        let root = unsafe { &*(root as *const SdtHeader) };
+        if root.length() < core::mem::size_of::<SdtHeader>() || !root.checksum_is_valid() {
+            return Err(AcpiError::InvalidRootTable);
+        }
+
+        Ok(Acpi {
+            rsdp,
+            source,
+            root,
+            entry_size,
+            mapped: Vec::new(),
+        })
+    }
+
+    pub fn rsdp(&self) -> &'static Rsdp {
+        self.rsdp
+    }
+
+    pub fn source(&self) -> RsdpSource {
+        self.source
+    }
+
+    pub fn root(&self) -> &'static SdtHeader {
+        self.root
+    }
+
+    pub fn tables(&self) -> impl Iterator<Item = &'static SdtHeader> + '_ {
+        self.table_addresses().map(|address| self.table_at(address))
+    }
+
+    fn table_at(&self, address: usize) -> &'static SdtHeader {
+        let mapped = self
+            .mapped
+            .iter()
+            .find(|(physical, _)| *physical == address);
+        match mapped {
+            Some((_, table)) => table,
+            None => unsafe { &*(address as *const SdtHeader) },
+        }
+    }
+
+    // Physical addresses of the tables the root table lists
+    fn table_addresses(&self) -> impl Iterator<Item = usize> + '_ {
+        let entries_start = self.root as *const _ as usize + core::mem::size_of::<SdtHeader>();
+        let count = (self.root.length() - core::mem::size_of::<SdtHeader>()) / self.entry_size;
+
+        (0..count).filter_map(move |i| {
+            let entry = entries_start + i * self.entry_size;
+            let address = if self.entry_size == 8 {
+                unsafe { core::ptr::read_unaligned(entry as *const u64) }
+            } else {
+                unsafe { core::ptr::read_unaligned(entry as *const u32) as u64 }
+            };
+
+            // Tables above 4 GiB are out of reach for a 32-bit kernel
+            if address > u32::MAX as u64 {
+                return None;
+            }
+
+            Some(address as usize)
+        })
+    }
+
+    pub fn find(&self, signature: &[u8; 4]) -> Option<&'static SdtHeader> {
+        self.tables()
+            .find(|table| &table.signature == signature && table.checksum_is_valid())
+    }
+
+    fn find_as<T>(&self, signature: &[u8; 4]) -> Option<&'static T> {
+        self.find(signature)
+            .filter(|header| header.length() >= core::mem::size_of::<T>())
+            .map(|header| unsafe { &*(header as *const _ as *const T) })
+    }
+
+    pub fn madt(&self) -> Option<&'static Madt> {
+        self.find_as(Madt::SIGNATURE)
+    }
+
+    pub fn fadt(&self) -> Option<&'static Fadt> {
+        self.find_as(Fadt::SIGNATURE)
+    }
+
+    pub fn hpet(&self) -> Option<&'static Hpet> {
+        self.find_as(Hpet::SIGNATURE)
+    }
+
+    pub fn mcfg(&self) -> Option<&'static Mcfg> {
+        self.find_as(Mcfg::SIGNATURE)
+    }
+}
+
+pub fn init(boot_info: &BootInformation) -> Result<&'static Acpi, AcpiError> {
+    let acpi = Acpi::from_boot_info(boot_info)?;
+
+    Ok(unsafe { ACPI.insert(acpi) })
+}
+
+// Maps the whole table at `physical`, its header says how long that is
+fn map_table(physical: usize) -> &'static SdtHeader {
+    let map = |length| {
+        let table = vmm::map_physical("ACPI", physical, length, EntryFlags::NO_EXECUTE)
+            .expect("Out of kernel virtual memory");
+        unsafe { &*(table as *const SdtHeader) }
+    };
+
+    let header_size = core::mem::size_of::<SdtHeader>();
+    let header = map(header_size);
+    let length = header.length();
+    vmm::unmap(header as *const _ as usize, header_size);
+
+    map(length)
+}
+
+// The kernel's directory doesn't map the low half, where the firmware keeps the tables, so they
+// are mapped again in the kernel's part of the address space. Needs `mem::init`
+pub fn map_tables() {
+    let Some(acpi) = (unsafe { ACPI.as_mut() }) else {
+        return;
+    };
+
+    // An RSDP copied into the multiboot information is already in the higher half
+    let rsdp = acpi.rsdp as *const _ as usize;
+    if rsdp < KERNEL_OFFSET {
+        let address = vmm::map_physical(
+            "ACPI",
+            rsdp,
+            core::mem::size_of::<Rsdp>(),
+            EntryFlags::NO_EXECUTE,
+        )
+        .expect("Out of kernel virtual memory");
+        acpi.rsdp = unsafe { &*(address as *const Rsdp) };
+    }
+
+    acpi.root = map_table(acpi.root as *const _ as usize);
+
+    let mapped = acpi
+        .table_addresses()
+        .map(|address| (address, map_table(address)))
+        .collect();
+    acpi.mapped = mapped;
+}
+
+pub fn get() -> Option<&'static Acpi> {
+    unsafe { ACPI.as_ref() }
+}
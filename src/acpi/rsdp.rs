@@ -0,0 +1,100 @@
+use multiboot2::BootInformation;
+
+const SIGNATURE: &[u8; 8] = b"RSD PTR ";
+const V1_LENGTH: usize = 20;
+
+const EBDA_SEGMENT_POINTER: usize = 0x40E;
+const EBDA_SEARCH_LENGTH: usize = 1024;
+const BIOS_AREA_START: usize = 0xE0000;
+const BIOS_AREA_END: usize = 0x100000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RsdpSource {
+    MultibootV2,
+    MultibootV1,
+    Ebda,
+    BiosArea,
+}
+
+#[repr(C, packed)]
+pub struct Rsdp {
+    pub signature: [u8; 8],
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub revision: u8,
+    pub rsdt_address: u32,
+    // Only valid for revision 2 and later
+    pub length: u32,
+    pub xsdt_address: u64,
+    pub extended_checksum: u8,
+    pub reserved: [u8; 3],
+}
+
+impl Rsdp {
+    pub fn locate(boot_info: &BootInformation) -> Option<(&'static Rsdp, RsdpSource)> {
+        // The multiboot2 tags carry a copy of the RSDP right after their 8 byte tag header
+        let from_tag = |tag_address: usize| unsafe { &*((tag_address + 8) as *const Rsdp) };
+
+        if let Some(tag) = boot_info.rsdp_v2_tag() {
+            return Some((from_tag(tag as *const _ as usize), RsdpSource::MultibootV2));
+        }
+
+        if let Some(tag) = boot_info.rsdp_v1_tag() {
+            return Some((from_tag(tag as *const _ as usize), RsdpSource::MultibootV1));
+        }
+
+        let ebda = unsafe { core::ptr::read_unaligned(EBDA_SEGMENT_POINTER as *const u16) };
+        let ebda = (ebda as usize) << 4;
+        if ebda != 0 {
+            if let Some(rsdp) = Self::scan(ebda, ebda + EBDA_SEARCH_LENGTH) {
+                return Some((rsdp, RsdpSource::Ebda));
+            }
+        }
+
+        Self::scan(BIOS_AREA_START, BIOS_AREA_END).map(|rsdp| (rsdp, RsdpSource::BiosArea))
+    }
+
+    fn scan(start: usize, end: usize) -> Option<&'static Rsdp> {
+        (start..end)
+            .step_by(16)
+            .map(|address| unsafe { &*(address as *const Rsdp) })
+            .find(|rsdp| &rsdp.signature == SIGNATURE && rsdp.checksum_is_valid())
+    }
+
+    pub fn checksum_is_valid(&self) -> bool {
+        if !checksum(self as *const _ as usize, V1_LENGTH) {
+            return false;
+        }
+
+        self.revision < 2 || checksum(self as *const _ as usize, self.length as usize)
+    }
+
+    pub fn oem_id(&self) -> &str {
+        core::str::from_utf8(&self.oem_id).unwrap_or("??????")
+    }
+
+    pub fn revision(&self) -> u8 {
+        self.revision
+    }
+
+    // The XSDT is preferred, but a 32-bit kernel can only use it if it lies below 4 GiB
+    pub fn xsdt_address(&self) -> Option<usize> {
+        let address = self.xsdt_address;
+
+        if self.revision >= 2 && address != 0 && address <= u32::MAX as u64 {
+            Some(address as usize)
+        } else {
+            None
+        }
+    }
+
+    pub fn rsdt_address(&self) -> usize {
+        self.rsdt_address as usize
+    }
+}
+
+pub(super) fn checksum(address: usize, length: usize) -> bool {
+    let bytes = unsafe { core::slice::from_raw_parts(address as *const u8, length) };
+
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) == 0
+}
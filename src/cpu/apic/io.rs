@@ -0,0 +1,135 @@
+const REG_SELECT: usize = 0x00;
+const REG_WINDOW: usize = 0x10;
+
+const REG_ID: u32 = 0x00;
+const REG_VERSION: u32 = 0x01;
+const REG_REDIRECTION_BASE: u32 = 0x10;
+
+const REDIRECTION_MASKED: u64 = 1 << 16;
+const REDIRECTION_LEVEL_TRIGGERED: u64 = 1 << 15;
+const REDIRECTION_ACTIVE_LOW: u64 = 1 << 13;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Polarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerMode {
+    Edge,
+    Level,
+}
+
+#[derive(Clone, Copy)]
+pub struct IoApic {
+    base: usize,
+    gsi_base: u32,
+}
+
+impl IoApic {
+    // Bytes of register space
+    pub const SIZE: usize = 0x20;
+
+    pub const fn new(base: usize, gsi_base: u32) -> IoApic {
+        IoApic { base, gsi_base }
+    }
+
+    unsafe fn read(&self, reg: u32) -> u32 {
+        ((self.base + REG_SELECT) as *mut u32).write_volatile(reg);
+        ((self.base + REG_WINDOW) as *const u32).read_volatile()
+    }
+
+    unsafe fn write(&self, reg: u32, value: u32) {
+        ((self.base + REG_SELECT) as *mut u32).write_volatile(reg);
+        ((self.base + REG_WINDOW) as *mut u32).write_volatile(value);
+    }
+
+    pub fn base(&self) -> usize {
+        self.base
+    }
+
+    pub unsafe fn set_base(&mut self, base: usize) {
+        self.base = base;
+    }
+
+    pub fn id(&self) -> u8 {
+        unsafe { ((self.read(REG_ID) >> 24) & 0xF) as u8 }
+    }
+
+    pub fn version(&self) -> u8 {
+        unsafe { (self.read(REG_VERSION) & 0xFF) as u8 }
+    }
+
+    pub fn redirection_entries(&self) -> u32 {
+        unsafe { ((self.read(REG_VERSION) >> 16) & 0xFF) + 1 }
+    }
+
+    pub fn handles(&self, gsi: u32) -> bool {
+        gsi >= self.gsi_base && gsi < self.gsi_base + self.redirection_entries()
+    }
+
+    unsafe fn read_redirection(&self, gsi: u32) -> u64 {
+        let reg = REG_REDIRECTION_BASE + (gsi - self.gsi_base) * 2;
+
+        (self.read(reg) as u64) | ((self.read(reg + 1) as u64) << 32)
+    }
+
+    unsafe fn write_redirection(&self, gsi: u32, entry: u64) {
+        let reg = REG_REDIRECTION_BASE + (gsi - self.gsi_base) * 2;
+
+        // Write the high half first so the entry is never live with a stale destination
+        self.write(reg + 1, (entry >> 32) as u32);
+        self.write(reg, entry as u32);
+    }
+
+    pub unsafe fn route(
+        &self,
+        gsi: u32,
+        vector: u8,
+        destination: u8,
+        polarity: Polarity,
+        trigger: TriggerMode,
+    ) {
+        let mut entry = REDIRECTION_MASKED | vector as u64 | ((destination as u64) << 56);
+        if polarity == Polarity::ActiveLow {
+            entry |= REDIRECTION_ACTIVE_LOW;
+        }
+        if trigger == TriggerMode::Level {
+            entry |= REDIRECTION_LEVEL_TRIGGERED;
+        }
+
+        self.write_redirection(gsi, entry);
+    }
+
+    pub unsafe fn set_masked(&self, gsi: u32, masked: bool) {
+        let entry = self.read_redirection(gsi);
+        let entry = if masked {
+            entry | REDIRECTION_MASKED
+        } else {
+            entry & !REDIRECTION_MASKED
+        };
+
+        self.write_redirection(gsi, entry);
+    }
+
+    pub unsafe fn mask_all(&self) {
+        for i in 0..self.redirection_entries() {
+            self.set_masked(self.gsi_base + i, true);
+        }
+    }
+}
+
+impl core::fmt::Debug for IoApic {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "IoApic {{ id: {}, version: 0x{:02X}, base: 0x{:08X}, gsi: {}..{} }}",
+            self.id(),
+            self.version(),
+            self.base,
+            self.gsi_base,
+            self.gsi_base + self.redirection_entries()
+        )
+    }
+}
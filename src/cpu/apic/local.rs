@@ -0,0 +1,170 @@
+use crate::cpu::msr::{rdmsr, wrmsr, IA32_APIC_BASE, IA32_TSC_DEADLINE};
+
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+const APIC_BASE_X2APIC: u64 = 1 << 10;
+const APIC_BASE_ADDRESS_MASK: u64 = 0xFFFFF000;
+
+const X2APIC_MSR_BASE: u32 = 0x800;
+
+pub const REG_ID: u32 = 0x20;
+pub const REG_VERSION: u32 = 0x30;
+pub const REG_TPR: u32 = 0x80;
+pub const REG_EOI: u32 = 0xB0;
+pub const REG_SVR: u32 = 0xF0;
+pub const REG_ESR: u32 = 0x280;
+pub const REG_ICR_LOW: u32 = 0x300;
+pub const REG_ICR_HIGH: u32 = 0x310;
+pub const REG_LVT_TIMER: u32 = 0x320;
+pub const REG_LVT_LINT0: u32 = 0x350;
+pub const REG_LVT_LINT1: u32 = 0x360;
+pub const REG_LVT_ERROR: u32 = 0x370;
+
+pub const LVT_MASKED: u32 = 1 << 16;
+pub const LVT_DELIVERY_NMI: u32 = 0b100 << 8;
+pub const LVT_ACTIVE_LOW: u32 = 1 << 13;
+pub const LVT_LEVEL_TRIGGERED: u32 = 1 << 15;
+pub const LVT_TIMER_TSC_DEADLINE: u32 = 0b10 << 17;
+
+const SVR_ENABLE: u32 = 1 << 8;
+
+const ICR_DELIVERY_INIT: u32 = 0b101 << 8;
+const ICR_DELIVERY_STARTUP: u32 = 0b110 << 8;
+const ICR_DELIVERY_PENDING: u32 = 1 << 12;
+const ICR_LEVEL_ASSERT: u32 = 1 << 14;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    XApic { base: usize },
+    X2Apic,
+}
+
+pub struct LocalApic {
+    mode: Mode,
+}
+
+impl LocalApic {
+    pub unsafe fn enable(x2apic: bool) -> LocalApic {
+        let mut base = rdmsr(IA32_APIC_BASE) | APIC_BASE_ENABLE;
+        if x2apic {
+            base |= APIC_BASE_X2APIC;
+        }
+        wrmsr(IA32_APIC_BASE, base);
+
+        let mode = if x2apic {
+            Mode::X2Apic
+        } else {
+            Mode::XApic {
+                base: (base & APIC_BASE_ADDRESS_MASK) as usize,
+            }
+        };
+
+        LocalApic { mode }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub unsafe fn read(&self, reg: u32) -> u32 {
+        match self.mode {
+            Mode::XApic { base } => ((base + reg as usize) as *const u32).read_volatile(),
+            Mode::X2Apic => rdmsr(X2APIC_MSR_BASE + (reg >> 4)) as u32,
+        }
+    }
+
+    pub unsafe fn write(&self, reg: u32, value: u32) {
+        match self.mode {
+            Mode::XApic { base } => ((base + reg as usize) as *mut u32).write_volatile(value),
+            Mode::X2Apic => wrmsr(X2APIC_MSR_BASE + (reg >> 4), value as u64),
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        let id = unsafe { self.read(REG_ID) };
+
+        match self.mode {
+            Mode::XApic { .. } => id >> 24,
+            Mode::X2Apic => id,
+        }
+    }
+
+    pub fn version(&self) -> u8 {
+        unsafe { (self.read(REG_VERSION) & 0xFF) as u8 }
+    }
+
+    pub unsafe fn initialize(&self, spurious_vector: u8) {
+        // Mask every local interrupt source, the NMI lines get configured from the MADT
+        self.write(REG_LVT_TIMER, LVT_MASKED);
+        self.write(REG_LVT_LINT0, LVT_MASKED);
+        self.write(REG_LVT_LINT1, LVT_MASKED);
+        self.write(REG_LVT_ERROR, LVT_MASKED);
+
+        // Clear pending errors, the ESR has to be written before it can be read
+        self.write(REG_ESR, 0);
+        self.write(REG_ESR, 0);
+
+        // Accept every priority class
+        self.write(REG_TPR, 0);
+
+        self.write(REG_SVR, SVR_ENABLE | spurious_vector as u32);
+    }
+
+    pub unsafe fn set_nmi(&self, lint: u8, flags: u16) {
+        let mut lvt = LVT_DELIVERY_NMI;
+        if flags & 0b11 == 0b11 {
+            lvt |= LVT_ACTIVE_LOW;
+        }
+        if (flags >> 2) & 0b11 == 0b11 {
+            lvt |= LVT_LEVEL_TRIGGERED;
+        }
+
+        match lint {
+            0 => self.write(REG_LVT_LINT0, lvt),
+            1 => self.write(REG_LVT_LINT1, lvt),
+            _ => {}
+        }
+    }
+
+    pub unsafe fn enable_tsc_deadline(&self, vector: u8) {
+        self.write(REG_LVT_TIMER, LVT_TIMER_TSC_DEADLINE | vector as u32);
+    }
+
+    // Fires the timer vector once the TSC reaches `deadline`, zero disarms the timer
+    pub unsafe fn set_tsc_deadline(&self, deadline: u64) {
+        wrmsr(IA32_TSC_DEADLINE, deadline);
+    }
+
+    pub unsafe fn send_ipi(&self, destination: u32, command: u32) {
+        match self.mode {
+            Mode::XApic { .. } => {
+                self.write(REG_ICR_HIGH, destination << 24);
+                self.write(REG_ICR_LOW, command);
+
+                while self.read(REG_ICR_LOW) & ICR_DELIVERY_PENDING != 0 {
+                    core::hint::spin_loop();
+                }
+            }
+            // The x2APIC ICR is a single 64-bit MSR without a delivery status bit
+            Mode::X2Apic => wrmsr(
+                X2APIC_MSR_BASE + (REG_ICR_LOW >> 4),
+                ((destination as u64) << 32) | command as u64,
+            ),
+        }
+    }
+
+    pub unsafe fn send_init(&self, destination: u32) {
+        self.send_ipi(destination, ICR_DELIVERY_INIT | ICR_LEVEL_ASSERT);
+    }
+
+    // Starts the target in real mode at `page * 0x1000`
+    pub unsafe fn send_startup(&self, destination: u32, page: u8) {
+        self.send_ipi(
+            destination,
+            ICR_DELIVERY_STARTUP | ICR_LEVEL_ASSERT | page as u32,
+        );
+    }
+
+    pub fn end_of_interrupt(&self) {
+        unsafe { self.write(REG_EOI, 0) };
+    }
+}
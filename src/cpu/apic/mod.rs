@@ -0,0 +1,238 @@
+mod io;
+mod local;
+
+pub use io::*;
+pub use local::*;
+
+use super::idt::{self, InterruptStackFrame};
+use super::irq::{self, IRQ_BASE, IRQ_COUNT};
+use super::pic::CASCADE_LINE;
+use crate::acpi::{Madt, MadtEntry};
+use crate::mem::vmm;
+
+pub const TIMER_VECTOR: u8 = 0xF0;
+pub const HPET_VECTOR: u8 = 0xF1;
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+const MAX_IO_APICS: usize = 8;
+
+#[derive(Debug, Clone, Copy)]
+struct IsaRoute {
+    gsi: u32,
+    polarity: Polarity,
+    trigger: TriggerMode,
+}
+
+impl IsaRoute {
+    const fn identity(line: u8) -> IsaRoute {
+        IsaRoute {
+            gsi: line as u32,
+            polarity: Polarity::ActiveHigh,
+            trigger: TriggerMode::Edge,
+        }
+    }
+
+    fn from_override(gsi: u32, flags: u16) -> IsaRoute {
+        // ISA interrupts default to active high, edge triggered
+        let polarity = match flags & 0b11 {
+            0b11 => Polarity::ActiveLow,
+            _ => Polarity::ActiveHigh,
+        };
+        let trigger = match (flags >> 2) & 0b11 {
+            0b11 => TriggerMode::Level,
+            _ => TriggerMode::Edge,
+        };
+
+        IsaRoute {
+            gsi,
+            polarity,
+            trigger,
+        }
+    }
+}
+
+static mut LOCAL_APIC: Option<LocalApic> = None;
+static mut IO_APICS: [Option<IoApic>; MAX_IO_APICS] = [None; MAX_IO_APICS];
+// None for lines without an I/O APIC pin, the cascade line and lines whose GSI an override took
+static mut ISA_ROUTES: [Option<IsaRoute>; IRQ_COUNT as usize] = [None; IRQ_COUNT as usize];
+
+extern "x86-interrupt" fn spurious(_frame: InterruptStackFrame) {
+    // Spurious interrupts must not be acknowledged
+}
+
+pub fn is_enabled() -> bool {
+    unsafe { LOCAL_APIC.is_some() }
+}
+
+pub fn local() -> &'static LocalApic {
+    unsafe { LOCAL_APIC.as_ref().expect("Local APIC is not initialized") }
+}
+
+pub fn io_apics() -> impl Iterator<Item = &'static IoApic> {
+    unsafe { IO_APICS.iter().flatten() }
+}
+
+fn io_apic_for(gsi: u32) -> Option<&'static IoApic> {
+    unsafe {
+        IO_APICS
+            .iter()
+            .flatten()
+            .find(|io_apic| io_apic.handles(gsi))
+    }
+}
+
+// Lines without a route have nothing to mask
+pub fn mask(line: u8) {
+    let Some(route) = (unsafe { ISA_ROUTES[line as usize] }) else {
+        return;
+    };
+
+    if let Some(io_apic) = io_apic_for(route.gsi) {
+        unsafe { io_apic.set_masked(route.gsi, true) };
+    }
+}
+
+// Returns false if the line isn't routed to any I/O APIC, e.g. because an interrupt source
+// override took its GSI
+pub fn unmask(line: u8) -> bool {
+    let Some(route) = (unsafe { ISA_ROUTES[line as usize] }) else {
+        return false;
+    };
+
+    match io_apic_for(route.gsi) {
+        Some(io_apic) => {
+            unsafe { io_apic.set_masked(route.gsi, false) };
+            true
+        }
+        None => false,
+    }
+}
+
+// Routes a GSI that isn't an ISA IRQ, e.g. an HPET comparator, straight to an IDT vector
+pub fn route_gsi(gsi: u32, vector: u8, polarity: Polarity, trigger: TriggerMode) {
+    let io_apic = io_apic_for(gsi).unwrap_or_else(|| panic!("No I/O APIC handles GSI {}", gsi));
+
+    super::without_interrupts(|| unsafe {
+        io_apic.route(gsi, vector, local().id() as u8, polarity, trigger);
+        io_apic.set_masked(gsi, false);
+    });
+}
+
+pub fn end_of_interrupt() {
+    local().end_of_interrupt();
+}
+
+unsafe fn set_nmis(local: &LocalApic, madt: &Madt) {
+    let processor_id = madt.entries().find_map(|entry| match entry {
+        MadtEntry::LocalApic {
+            processor_id,
+            apic_id,
+            ..
+        } if apic_id as u32 == local.id() => Some(processor_id),
+        _ => None,
+    });
+
+    for entry in madt.entries() {
+        if let MadtEntry::LocalApicNmi {
+            processor_id: target,
+            flags,
+            lint,
+        } = entry
+        {
+            if target == 0xFF || Some(target) == processor_id {
+                local.set_nmi(lint, flags);
+            }
+        }
+    }
+}
+
+pub fn init(madt: &Madt, x2apic: bool) {
+    super::without_interrupts(|| unsafe {
+        idt::set_handler(SPURIOUS_VECTOR, spurious);
+
+        let local = LOCAL_APIC.insert(LocalApic::enable(x2apic));
+        local.initialize(SPURIOUS_VECTOR);
+
+        set_nmis(local, madt);
+
+        let mut routes = [None; IRQ_COUNT as usize];
+        // GSIs below 32 that an override redirected an ISA IRQ to
+        let mut claimed = 0u32;
+
+        let mut io_apic_count = 0;
+        for entry in madt.entries() {
+            match entry {
+                MadtEntry::IoApic {
+                    address, gsi_base, ..
+                } if io_apic_count < MAX_IO_APICS => {
+                    let io_apic = IoApic::new(address as usize, gsi_base);
+                    io_apic.mask_all();
+
+                    IO_APICS[io_apic_count] = Some(io_apic);
+                    io_apic_count += 1;
+                }
+                MadtEntry::InterruptSourceOverride {
+                    bus: 0,
+                    source,
+                    gsi,
+                    flags,
+                } if source < IRQ_COUNT => {
+                    routes[source as usize] = Some(IsaRoute::from_override(gsi, flags));
+                    if gsi < 32 {
+                        claimed |= 1 << gsi;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // Identity mapped lines lose their pin to an override, e.g. IRQ 0 is usually on GSI 2,
+        // and the cascade line never raises interrupts of its own
+        for line in 0..IRQ_COUNT {
+            if routes[line as usize].is_none() && claimed & (1 << line) == 0 {
+                routes[line as usize] = Some(IsaRoute::identity(line));
+            }
+        }
+        routes[CASCADE_LINE as usize] = None;
+
+        let destination = local.id() as u8;
+        for (line, route) in routes.iter().enumerate() {
+            let Some(route) = route else {
+                continue;
+            };
+
+            if let Some(io_apic) = io_apic_for(route.gsi) {
+                ISA_ROUTES[line] = Some(*route);
+                io_apic.route(
+                    route.gsi,
+                    IRQ_BASE + line as u8,
+                    destination,
+                    route.polarity,
+                    route.trigger,
+                );
+            }
+        }
+
+        irq::use_apic();
+    });
+}
+
+// The I/O APICs are used through the boot identity mapping until kernel virtual memory is set
+// up, the kernel's page tables leave them out
+pub fn map_io_apics() {
+    super::without_interrupts(|| unsafe {
+        for io_apic in IO_APICS.iter_mut().flatten() {
+            io_apic.set_base(vmm::map_mmio(io_apic.base(), IoApic::SIZE));
+        }
+    });
+}
+
+// Enables the local APIC of an application processor in the same mode as the BSP's
+pub fn init_ap(madt: &Madt) {
+    super::without_interrupts(|| unsafe {
+        let local = LocalApic::enable(local().mode() == Mode::X2Apic);
+        local.initialize(SPURIOUS_VECTOR);
+
+        set_nmis(&local, madt);
+    });
+}
@@ -26,6 +26,7 @@ pub struct Basic {
     pub manufacturer: Manufacturer,
     pub basic_info: Option<BasicInfo>,
     pub info_and_bits: Option<BasicInfoAndBits>,
+    pub tsc_info: Option<TscInfo>,
 }
 
 impl Basic {
@@ -52,6 +53,11 @@ impl Basic {
             } else {
                 Some(Basic::read_info_and_bits())
             },
+            tsc_info: if eax < 0x15 {
+                None
+            } else {
+                Some(Basic::read_tsc_info())
+            },
         }
     }
 
@@ -77,18 +83,45 @@ impl Basic {
             ecx: BasicInfoAndBitsECX::from_bits_truncate(ecx),
         }
     }
+
+    fn read_tsc_info() -> TscInfo {
+        let AnyCPUID { eax, ebx, ecx, .. } = do_cpuid(0x15);
+
+        TscInfo {
+            denominator: eax,
+            numerator: ebx,
+            crystal_frequency: ecx,
+        }
+    }
 }
 
 impl core::fmt::Debug for Basic {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(
             f,
-            "{{ max_basic_fn: 0x{:016X}, manufacturer: {}, basic_info: {:?}, info_and_bits: {:?} }}",
-            self.max_basic_fn, self.manufacturer, self.basic_info, self.info_and_bits
+            "{{ max_basic_fn: 0x{:016X}, manufacturer: {}, basic_info: {:?}, info_and_bits: {:?}, tsc_info: {:?} }}",
+            self.max_basic_fn, self.manufacturer, self.basic_info, self.info_and_bits, self.tsc_info
         )
     }
 }
 
+#[derive(Debug)]
+pub struct TscInfo {
+    pub denominator: u32,
+    pub numerator: u32,
+    pub crystal_frequency: u32,
+}
+
+impl TscInfo {
+    pub fn tsc_frequency(&self) -> Option<u64> {
+        if self.denominator == 0 || self.numerator == 0 || self.crystal_frequency == 0 {
+            return None;
+        }
+
+        Some(self.crystal_frequency as u64 * self.numerator as u64 / self.denominator as u64)
+    }
+}
+
 #[derive(Debug)]
 #[allow(clippy::upper_case_acronyms)]
 pub enum Type {
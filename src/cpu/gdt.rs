@@ -0,0 +1,306 @@
+use core::arch::asm;
+
+use super::{idt, DescriptorTablePointer, MAX_CPUS};
+use crate::mem;
+use crate::misc::klog::kinfo;
+use crate::vga::{print, println};
+
+pub const KERNEL_CODE_SELECTOR: u16 = 0x08;
+pub const KERNEL_DATA_SELECTOR: u16 = 0x10;
+#[allow(dead_code)]
+pub const USER_CODE_SELECTOR: u16 = 0x18 | 3;
+#[allow(dead_code)]
+pub const USER_DATA_SELECTOR: u16 = 0x20 | 3;
+pub const TSS_SELECTOR: u16 = 0x28;
+pub const DOUBLE_FAULT_TSS_SELECTOR: u16 = 0x30;
+
+const DOUBLE_FAULT_STACK_SIZE: usize = 16 * 1024;
+
+const EMPTY_GDT: GlobalDescriptorTable = GlobalDescriptorTable::new();
+const EMPTY_DOUBLE_FAULT_STACK: DoubleFaultStack = DoubleFaultStack([0; DOUBLE_FAULT_STACK_SIZE]);
+
+// Every CPU gets its own GDT, since the busy bit of its TSS descriptors is per CPU
+static mut GDT: [GlobalDescriptorTable; MAX_CPUS] = [EMPTY_GDT; MAX_CPUS];
+static mut TSS: [TaskStateSegment; MAX_CPUS] = [TaskStateSegment::new(); MAX_CPUS];
+static mut DOUBLE_FAULT_TSS: [TaskStateSegment; MAX_CPUS] = [TaskStateSegment::new(); MAX_CPUS];
+static mut DOUBLE_FAULT_STACK: [DoubleFaultStack; MAX_CPUS] = [EMPTY_DOUBLE_FAULT_STACK; MAX_CPUS];
+
+#[repr(C, align(16))]
+struct DoubleFaultStack([u8; DOUBLE_FAULT_STACK_SIZE]);
+
+#[derive(Clone, Copy)]
+#[repr(transparent)]
+pub struct SegmentDescriptor {
+    inner: u64,
+}
+
+impl SegmentDescriptor {
+    pub const fn null() -> Self {
+        Self { inner: 0 }
+    }
+
+    pub const fn new(base: u32, limit: u32, access: u8, flags: u8) -> Self {
+        let inner = (((base as u64) & 0xFF000000) << 32)
+            | (((flags as u64) & 0xF) << 52)
+            | (((limit as u64) & 0xF0000) << 32)
+            | ((access as u64) << 40)
+            | (((base as u64) & 0x00FFFFFF) << 16)
+            | ((limit as u64) & 0x0FFFF);
+
+        Self { inner }
+    }
+
+    pub const fn code(dpl: u8) -> Self {
+        // present, code/data, executable, readable
+        let access = 0x80 | ((dpl & 0x3) << 5) | 0x10 | 0x08 | 0x02;
+
+        // 4k granularity, 32-bit
+        Self::new(0, 0xFFFFF, access, 0xC)
+    }
+
+    pub const fn data(dpl: u8) -> Self {
+        // present, code/data, writable
+        let access = 0x80 | ((dpl & 0x3) << 5) | 0x10 | 0x02;
+
+        // 4k granularity, 32-bit
+        Self::new(0, 0xFFFFF, access, 0xC)
+    }
+
+    pub fn tss(tss: &'static TaskStateSegment) -> Self {
+        // present, 32-bit available TSS
+        let access = 0x80 | 0x09;
+
+        Self::new(
+            tss as *const _ as u32,
+            (core::mem::size_of::<TaskStateSegment>() - 1) as u32,
+            access,
+            0x0,
+        )
+    }
+
+    pub fn base(&self) -> u32 {
+        (((self.inner >> 32) & 0xFF000000) | ((self.inner >> 16) & 0x00FFFFFF)) as u32
+    }
+
+    pub fn limit(&self) -> u32 {
+        (((self.inner >> 32) & 0xF0000) | (self.inner & 0x0FFFF)) as u32
+    }
+
+    pub fn access(&self) -> u8 {
+        ((self.inner >> 40) & 0xFF) as u8
+    }
+
+    pub fn flags(&self) -> u8 {
+        ((self.inner >> 52) & 0xF) as u8
+    }
+}
+
+impl core::fmt::Debug for SegmentDescriptor {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "SegmentDescriptor {{ base: 0x{:08X}, limit: 0x{:05X}, access: 0x{:02X}, flags: 0x{:01X} }}",
+            self.base(),
+            self.limit(),
+            self.access(),
+            self.flags()
+        )
+    }
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct TaskStateSegment {
+    pub link: u32,
+    pub esp0: u32,
+    pub ss0: u32,
+    pub esp1: u32,
+    pub ss1: u32,
+    pub esp2: u32,
+    pub ss2: u32,
+    pub cr3: u32,
+    pub eip: u32,
+    pub eflags: u32,
+    pub eax: u32,
+    pub ecx: u32,
+    pub edx: u32,
+    pub ebx: u32,
+    pub esp: u32,
+    pub ebp: u32,
+    pub esi: u32,
+    pub edi: u32,
+    pub es: u32,
+    pub cs: u32,
+    pub ss: u32,
+    pub ds: u32,
+    pub fs: u32,
+    pub gs: u32,
+    pub ldtr: u32,
+    pub trap: u16,
+    pub iomap_base: u16,
+}
+
+impl TaskStateSegment {
+    pub const fn new() -> Self {
+        Self {
+            link: 0,
+            esp0: 0,
+            ss0: 0,
+            esp1: 0,
+            ss1: 0,
+            esp2: 0,
+            ss2: 0,
+            cr3: 0,
+            eip: 0,
+            eflags: 0,
+            eax: 0,
+            ecx: 0,
+            edx: 0,
+            ebx: 0,
+            esp: 0,
+            ebp: 0,
+            esi: 0,
+            edi: 0,
+            es: 0,
+            cs: 0,
+            ss: 0,
+            ds: 0,
+            fs: 0,
+            gs: 0,
+            ldtr: 0,
+            trap: 0,
+            iomap_base: core::mem::size_of::<TaskStateSegment>() as u16,
+        }
+    }
+}
+
+impl core::fmt::Debug for TaskStateSegment {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "TaskStateSegment {{ eip: 0x{:08X}, esp: 0x{:08X}, ebp: 0x{:08X}, eflags: 0x{:08X}, cs: 0x{:04X}, ss: 0x{:04X} }}",
+            self.eip, self.esp, self.ebp, self.eflags, self.cs, self.ss
+        )
+    }
+}
+
+#[repr(C, align(8))]
+pub struct GlobalDescriptorTable {
+    entries: [SegmentDescriptor; 7],
+}
+
+impl GlobalDescriptorTable {
+    pub const fn new() -> Self {
+        Self {
+            entries: [
+                SegmentDescriptor::null(),
+                SegmentDescriptor::code(0),
+                SegmentDescriptor::data(0),
+                SegmentDescriptor::code(3),
+                SegmentDescriptor::data(3),
+                SegmentDescriptor::null(),
+                SegmentDescriptor::null(),
+            ],
+        }
+    }
+
+    pub fn set(&mut self, selector: u16, descriptor: SegmentDescriptor) -> &mut Self {
+        self.entries[(selector >> 3) as usize] = descriptor;
+        self
+    }
+
+    pub unsafe fn load(&'static self) {
+        let pointer = DescriptorTablePointer {
+            limit: (core::mem::size_of::<Self>() - 1) as u16,
+            base: self as *const _ as u32,
+        };
+
+        asm!("lgdt [{}]", in(reg) &pointer, options(readonly, nostack, preserves_flags));
+
+        asm!(
+            "push {code}",
+            "lea {tmp}, [2f]",
+            "push {tmp}",
+            "retf",
+            "2:",
+            "mov ds, {data:x}",
+            "mov es, {data:x}",
+            "mov fs, {data:x}",
+            "mov gs, {data:x}",
+            "mov ss, {data:x}",
+
+            code = in(reg) KERNEL_CODE_SELECTOR as u32,
+            data = in(reg) KERNEL_DATA_SELECTOR as u32,
+            tmp = out(reg) _,
+        );
+    }
+}
+
+extern "C" fn double_fault_task(cpu: usize) -> ! {
+    // The CPU saved the interrupted state into the TSS we were running on
+    let previous = unsafe { TSS[cpu] };
+
+    println!();
+    kinfo!("CPU exception #DF (Double Fault), vector 8, CPU {}", cpu);
+    kinfo!("  Interrupted task: {:?}", previous);
+
+    // Running into a guard page leaves no stack for the #PF handler, which ends up here
+    let cr2 = idt::cr2();
+    if let Some(name) = mem::guard_region(cr2) {
+        panic!(
+            "Stack overflow, 0x{:08X} is in the guard page of the {}",
+            cr2, name
+        );
+    }
+
+    panic!("Unhandled CPU exception #DF (Double Fault)");
+}
+
+// The double fault task switch loads CR3 from the TSS, so it has to follow directory switches
+pub fn set_double_fault_cr3(cpu: usize, cr3: u32) {
+    unsafe { DOUBLE_FAULT_TSS[cpu].cr3 = cr3 };
+}
+
+pub fn init(cpu: usize) {
+    assert!(cpu < MAX_CPUS);
+
+    unsafe {
+        let tss = &mut TSS[cpu];
+        let double_fault_tss = &mut DOUBLE_FAULT_TSS[cpu];
+
+        tss.ss0 = KERNEL_DATA_SELECTOR as u32;
+
+        // The task switch pushes the error code below `esp`, which leaves the word at `esp`
+        // where `double_fault_task` expects its first argument
+        let stack = &mut DOUBLE_FAULT_STACK[cpu].0;
+        let stack_top = stack.as_mut_ptr() as u32 + DOUBLE_FAULT_STACK_SIZE as u32 - 4;
+        (stack_top as *mut u32).write(cpu as u32);
+
+        let cr3: u32;
+        asm!("mov {}, cr3", out(reg) cr3, options(nomem, nostack, preserves_flags));
+
+        double_fault_tss.cr3 = cr3;
+        double_fault_tss.eip = double_fault_task as usize as u32;
+        double_fault_tss.eflags = 0x2;
+        double_fault_tss.esp = stack_top;
+        double_fault_tss.ss0 = KERNEL_DATA_SELECTOR as u32;
+        double_fault_tss.esp0 = stack_top;
+        double_fault_tss.cs = KERNEL_CODE_SELECTOR as u32;
+        double_fault_tss.ds = KERNEL_DATA_SELECTOR as u32;
+        double_fault_tss.es = KERNEL_DATA_SELECTOR as u32;
+        double_fault_tss.fs = KERNEL_DATA_SELECTOR as u32;
+        double_fault_tss.gs = KERNEL_DATA_SELECTOR as u32;
+        double_fault_tss.ss = KERNEL_DATA_SELECTOR as u32;
+
+        GDT[cpu]
+            .set(TSS_SELECTOR, SegmentDescriptor::tss(&TSS[cpu]))
+            .set(
+                DOUBLE_FAULT_TSS_SELECTOR,
+                SegmentDescriptor::tss(&DOUBLE_FAULT_TSS[cpu]),
+            );
+
+        GDT[cpu].load();
+
+        asm!("ltr {:x}", in(reg) TSS_SELECTOR, options(nomem, nostack, preserves_flags));
+    }
+}
@@ -0,0 +1,339 @@
+use core::arch::asm;
+
+use bitflags::bitflags;
+
+use super::{gdt, DescriptorTablePointer};
+use crate::mem::{self, address_space::PageFaultError};
+use crate::misc::klog::kinfo;
+use crate::vga::{print, println};
+
+static mut IDT: InterruptDescriptorTable = InterruptDescriptorTable::new();
+
+pub type HandlerFunc = extern "x86-interrupt" fn(InterruptStackFrame);
+pub type HandlerFuncWithErrCode = extern "x86-interrupt" fn(InterruptStackFrame, u32);
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct InterruptStackFrame {
+    pub eip: u32,
+    pub cs: u32,
+    pub eflags: u32,
+}
+
+impl core::fmt::Debug for InterruptStackFrame {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "InterruptStackFrame {{ eip: 0x{:08X}, cs: 0x{:04X}, eflags: 0x{:08X} }}",
+            self.eip, self.cs, self.eflags
+        )
+    }
+}
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct PageFaultErrorCode: u32 {
+        // Set for protection violations, clear if the page wasn't present
+        const PRESENT = 1 << 0;
+        const WRITE = 1 << 1;
+        const USER = 1 << 2;
+        const RESERVED_BIT = 1 << 3;
+        const INSTRUCTION_FETCH = 1 << 4;
+        const PROTECTION_KEY = 1 << 5;
+        const SHADOW_STACK = 1 << 6;
+        const SGX = 1 << 15;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum GateType {
+    Task = 0x5,
+    Interrupt32 = 0xE,
+}
+
+#[derive(Clone, Copy)]
+#[repr(transparent)]
+pub struct IdtEntry {
+    inner: u64,
+}
+
+impl IdtEntry {
+    pub const fn missing() -> Self {
+        Self { inner: 0 }
+    }
+
+    pub fn new(offset: u32, selector: u16, gate_type: GateType, dpl: u8, present: bool) -> Self {
+        assert!(dpl < 4);
+
+        let inner = ((offset as u64 & 0xFFFF0000) << 32)
+            | ((present as u64) << 47)
+            | ((dpl as u64) << 45)
+            // 1 bit zero
+            | ((gate_type as u64) << 40)
+            // 8 bit reserved
+            | ((selector as u64) << 16)
+            | (offset as u64 & 0x0000FFFF);
+
+        unsafe { Self::new_raw(inner) }
+    }
+
+    pub unsafe fn new_raw(inner: u64) -> Self {
+        Self { inner }
+    }
+
+    pub fn offset(&self) -> u32 {
+        (((self.inner >> 32) & 0xFFFF0000) | (self.inner & 0x0000FFFF)) as u32
+    }
+
+    pub fn selector(&self) -> u16 {
+        ((self.inner >> 16) & 0xFFFF) as u16
+    }
+
+    pub fn dpl(&self) -> u8 {
+        ((self.inner >> 45) & 0x3) as u8
+    }
+
+    pub fn is_present(&self) -> bool {
+        (self.inner & (1 << 47)) != 0
+    }
+}
+
+impl core::fmt::Debug for IdtEntry {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("IdtEntry")
+            .field("offset", &format_args!("0x{:08X}", self.offset()))
+            .field("selector", &format_args!("0x{:04X}", self.selector()))
+            .field("dpl", &self.dpl())
+            .field("is_present", &self.is_present())
+            .finish()
+    }
+}
+
+#[repr(C, align(8))]
+pub struct InterruptDescriptorTable {
+    entries: [IdtEntry; 256],
+}
+
+impl InterruptDescriptorTable {
+    pub const fn new() -> Self {
+        Self {
+            entries: [IdtEntry::missing(); 256],
+        }
+    }
+
+    pub fn set_handler(&mut self, vector: u8, handler: HandlerFunc) -> &mut Self {
+        self.set_gate(vector, handler as usize)
+    }
+
+    pub fn set_handler_with_err_code(
+        &mut self,
+        vector: u8,
+        handler: HandlerFuncWithErrCode,
+    ) -> &mut Self {
+        self.set_gate(vector, handler as usize)
+    }
+
+    pub fn set_task_gate(&mut self, vector: u8, tss_selector: u16) -> &mut Self {
+        self.entries[vector as usize] = IdtEntry::new(0, tss_selector, GateType::Task, 0, true);
+        self
+    }
+
+    fn set_gate(&mut self, vector: u8, address: usize) -> &mut Self {
+        self.entries[vector as usize] = IdtEntry::new(
+            address as u32,
+            gdt::KERNEL_CODE_SELECTOR,
+            GateType::Interrupt32,
+            0,
+            true,
+        );
+        self
+    }
+
+    pub unsafe fn load(&'static self) {
+        let pointer = DescriptorTablePointer {
+            limit: (core::mem::size_of::<Self>() - 1) as u16,
+            base: self as *const _ as u32,
+        };
+
+        asm!("lidt [{}]", in(reg) &pointer, options(readonly, nostack, preserves_flags));
+    }
+}
+
+const EXCEPTIONS: [(&str, &str); 32] = [
+    ("#DE", "Divide Error"),
+    ("#DB", "Debug"),
+    ("NMI", "Non-maskable Interrupt"),
+    ("#BP", "Breakpoint"),
+    ("#OF", "Overflow"),
+    ("#BR", "Bound Range Exceeded"),
+    ("#UD", "Invalid Opcode"),
+    ("#NM", "Device Not Available"),
+    ("#DF", "Double Fault"),
+    ("---", "Coprocessor Segment Overrun"),
+    ("#TS", "Invalid TSS"),
+    ("#NP", "Segment Not Present"),
+    ("#SS", "Stack-Segment Fault"),
+    ("#GP", "General Protection Fault"),
+    ("#PF", "Page Fault"),
+    ("---", "Reserved"),
+    ("#MF", "x87 Floating-Point Exception"),
+    ("#AC", "Alignment Check"),
+    ("#MC", "Machine Check"),
+    ("#XM", "SIMD Floating-Point Exception"),
+    ("#VE", "Virtualization Exception"),
+    ("#CP", "Control Protection Exception"),
+    ("---", "Reserved"),
+    ("---", "Reserved"),
+    ("---", "Reserved"),
+    ("---", "Reserved"),
+    ("---", "Reserved"),
+    ("---", "Reserved"),
+    ("#HV", "Hypervisor Injection Exception"),
+    ("#VC", "VMM Communication Exception"),
+    ("#SX", "Security Exception"),
+    ("---", "Reserved"),
+];
+
+macro_rules! exception_handler {
+    ($name:ident, $vector:expr) => {
+        extern "x86-interrupt" fn $name(frame: InterruptStackFrame) {
+            exception($vector, None, &frame);
+        }
+    };
+    ($name:ident, $vector:expr, error_code) => {
+        extern "x86-interrupt" fn $name(frame: InterruptStackFrame, error_code: u32) {
+            exception($vector, Some(error_code), &frame);
+        }
+    };
+}
+
+exception_handler!(divide_error, 0);
+exception_handler!(debug, 1);
+exception_handler!(non_maskable_interrupt, 2);
+exception_handler!(breakpoint, 3);
+exception_handler!(overflow, 4);
+exception_handler!(bound_range_exceeded, 5);
+exception_handler!(invalid_opcode, 6);
+exception_handler!(device_not_available, 7);
+exception_handler!(coprocessor_segment_overrun, 9);
+exception_handler!(invalid_tss, 10, error_code);
+exception_handler!(segment_not_present, 11, error_code);
+exception_handler!(stack_segment_fault, 12, error_code);
+exception_handler!(general_protection_fault, 13, error_code);
+exception_handler!(reserved_15, 15);
+exception_handler!(x87_floating_point, 16);
+exception_handler!(alignment_check, 17, error_code);
+exception_handler!(machine_check, 18);
+exception_handler!(simd_floating_point, 19);
+exception_handler!(virtualization, 20);
+exception_handler!(control_protection, 21, error_code);
+exception_handler!(reserved_22, 22);
+exception_handler!(reserved_23, 23);
+exception_handler!(reserved_24, 24);
+exception_handler!(reserved_25, 25);
+exception_handler!(reserved_26, 26);
+exception_handler!(reserved_27, 27);
+exception_handler!(hypervisor_injection, 28);
+exception_handler!(vmm_communication, 29, error_code);
+exception_handler!(security, 30, error_code);
+exception_handler!(reserved_31, 31);
+
+pub fn cr2() -> usize {
+    let cr2: usize;
+    unsafe { asm!("mov {}, cr2", out(reg) cr2, options(nomem, nostack, preserves_flags)) };
+
+    cr2
+}
+
+extern "x86-interrupt" fn page_fault(frame: InterruptStackFrame, error_code: u32) {
+    let cr2 = cr2();
+    let error = PageFaultErrorCode::from_bits_truncate(error_code);
+
+    let fault = match mem::handle_page_fault(cr2, error) {
+        Ok(()) => return,
+        Err(fault) => fault,
+    };
+
+    print_exception(14, Some(error_code), &frame);
+    kinfo!("  Faulting address (CR2): 0x{:08X}", cr2);
+    kinfo!("  {:?}, {:?}", error, fault);
+
+    if let PageFaultError::Guard(name) = fault {
+        panic!(
+            "Stack overflow, 0x{:08X} is in the guard page of the {}",
+            cr2, name
+        );
+    }
+
+    let (mnemonic, name) = EXCEPTIONS[14];
+    panic!("Unhandled CPU exception {} ({})", mnemonic, name);
+}
+
+fn exception(vector: u8, error_code: Option<u32>, frame: &InterruptStackFrame) -> ! {
+    print_exception(vector, error_code, frame);
+
+    let (mnemonic, name) = EXCEPTIONS[vector as usize];
+    panic!("Unhandled CPU exception {} ({})", mnemonic, name);
+}
+
+fn print_exception(vector: u8, error_code: Option<u32>, frame: &InterruptStackFrame) {
+    let (mnemonic, name) = EXCEPTIONS[vector as usize];
+
+    println!();
+    kinfo!("CPU exception {} ({}), vector {}", mnemonic, name, vector);
+    if let Some(error_code) = error_code {
+        kinfo!("  Error code: 0x{:08X}", error_code);
+    }
+    kinfo!("  {:?}", frame);
+}
+
+pub fn set_handler(vector: u8, handler: HandlerFunc) {
+    super::without_interrupts(|| unsafe {
+        IDT.set_handler(vector, handler);
+    });
+}
+
+// Application processors share the BSP's IDT
+pub fn load() {
+    unsafe { IDT.load() };
+}
+
+pub fn init() {
+    unsafe {
+        IDT.set_handler(0, divide_error)
+            .set_handler(1, debug)
+            .set_handler(2, non_maskable_interrupt)
+            .set_handler(3, breakpoint)
+            .set_handler(4, overflow)
+            .set_handler(5, bound_range_exceeded)
+            .set_handler(6, invalid_opcode)
+            .set_handler(7, device_not_available)
+            .set_task_gate(8, gdt::DOUBLE_FAULT_TSS_SELECTOR)
+            .set_handler(9, coprocessor_segment_overrun)
+            .set_handler_with_err_code(10, invalid_tss)
+            .set_handler_with_err_code(11, segment_not_present)
+            .set_handler_with_err_code(12, stack_segment_fault)
+            .set_handler_with_err_code(13, general_protection_fault)
+            .set_handler_with_err_code(14, page_fault)
+            .set_handler(15, reserved_15)
+            .set_handler(16, x87_floating_point)
+            .set_handler_with_err_code(17, alignment_check)
+            .set_handler(18, machine_check)
+            .set_handler(19, simd_floating_point)
+            .set_handler(20, virtualization)
+            .set_handler_with_err_code(21, control_protection)
+            .set_handler(22, reserved_22)
+            .set_handler(23, reserved_23)
+            .set_handler(24, reserved_24)
+            .set_handler(25, reserved_25)
+            .set_handler(26, reserved_26)
+            .set_handler(27, reserved_27)
+            .set_handler(28, hypervisor_injection)
+            .set_handler_with_err_code(29, vmm_communication)
+            .set_handler_with_err_code(30, security)
+            .set_handler(31, reserved_31);
+
+        IDT.load();
+    }
+}
@@ -0,0 +1,151 @@
+use super::apic;
+use super::idt::{self, InterruptStackFrame};
+use super::pic::{ChainedPics, CASCADE_LINE};
+use crate::misc::klog::kinfo;
+use crate::vga::{print, println};
+
+pub const IRQ_BASE: u8 = 0x20;
+pub const IRQ_COUNT: u8 = 16;
+
+pub type IrqHandler = fn(line: u8);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Controller {
+    Pic,
+    Apic,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqError {
+    AlreadyRegistered,
+    // The I/O APIC has no route for the line
+    NoRoute,
+}
+
+static PICS: ChainedPics = ChainedPics::new(IRQ_BASE, IRQ_BASE + 8);
+static mut CONTROLLER: Controller = Controller::Pic;
+static mut HANDLERS: [Option<IrqHandler>; IRQ_COUNT as usize] = [None; IRQ_COUNT as usize];
+
+macro_rules! irq_stub {
+    ($name:ident, $line:expr) => {
+        extern "x86-interrupt" fn $name(_frame: InterruptStackFrame) {
+            dispatch($line);
+        }
+    };
+}
+
+irq_stub!(irq0, 0);
+irq_stub!(irq1, 1);
+irq_stub!(irq2, 2);
+irq_stub!(irq3, 3);
+irq_stub!(irq4, 4);
+irq_stub!(irq5, 5);
+irq_stub!(irq6, 6);
+irq_stub!(irq7, 7);
+irq_stub!(irq8, 8);
+irq_stub!(irq9, 9);
+irq_stub!(irq10, 10);
+irq_stub!(irq11, 11);
+irq_stub!(irq12, 12);
+irq_stub!(irq13, 13);
+irq_stub!(irq14, 14);
+irq_stub!(irq15, 15);
+
+const STUBS: [idt::HandlerFunc; IRQ_COUNT as usize] = [
+    irq0, irq1, irq2, irq3, irq4, irq5, irq6, irq7, irq8, irq9, irq10, irq11, irq12, irq13, irq14,
+    irq15,
+];
+
+fn dispatch(line: u8) {
+    unsafe {
+        if CONTROLLER == Controller::Pic && PICS.is_spurious(line) {
+            return;
+        }
+
+        if let Some(handler) = HANDLERS[line as usize] {
+            handler(line);
+        }
+
+        match CONTROLLER {
+            Controller::Pic => PICS.end_of_interrupt(line),
+            Controller::Apic => apic::end_of_interrupt(),
+        }
+    }
+}
+
+unsafe fn mask_line(line: u8) {
+    match CONTROLLER {
+        Controller::Pic => PICS.mask(line),
+        Controller::Apic => apic::mask(line),
+    }
+}
+
+unsafe fn unmask_line(line: u8) -> bool {
+    match CONTROLLER {
+        Controller::Pic => {
+            PICS.unmask(line);
+            true
+        }
+        Controller::Apic => apic::unmask(line),
+    }
+}
+
+pub fn register(line: u8, handler: IrqHandler) -> Result<(), IrqError> {
+    assert!(line < IRQ_COUNT);
+    assert!(line != CASCADE_LINE, "IRQ {} is used for cascading", line);
+
+    super::without_interrupts(|| unsafe {
+        if HANDLERS[line as usize].is_some() {
+            return Err(IrqError::AlreadyRegistered);
+        }
+
+        HANDLERS[line as usize] = Some(handler);
+
+        if !unmask_line(line) {
+            HANDLERS[line as usize] = None;
+            return Err(IrqError::NoRoute);
+        }
+
+        Ok(())
+    })
+}
+
+// Silences the line without dropping its handler
+#[allow(dead_code)]
+pub fn mask(line: u8) {
+    assert!(line < IRQ_COUNT);
+
+    super::without_interrupts(|| unsafe { mask_line(line) });
+}
+
+#[allow(dead_code)]
+pub fn unregister(line: u8) {
+    assert!(line < IRQ_COUNT);
+
+    super::without_interrupts(|| unsafe {
+        mask_line(line);
+        HANDLERS[line as usize] = None;
+    });
+}
+
+// Hands every registered line over from the legacy PICs to the I/O APIC routing set up by
+// `apic::init`, which has to be done with interrupts disabled.
+pub(super) unsafe fn use_apic() {
+    PICS.disable();
+    CONTROLLER = Controller::Apic;
+
+    for (line, handler) in HANDLERS.iter_mut().enumerate() {
+        if handler.is_some() && !apic::unmask(line as u8) {
+            kinfo!("IRQ {} has no I/O APIC route, dropping its handler", line);
+            *handler = None;
+        }
+    }
+}
+
+pub fn init() {
+    for (line, stub) in STUBS.iter().enumerate() {
+        idt::set_handler(IRQ_BASE + line as u8, *stub);
+    }
+
+    unsafe { PICS.initialize() };
+}
@@ -1 +1,53 @@
+use core::arch::asm;
+
+pub mod apic;
 pub mod cpuid;
+pub mod gdt;
+pub mod idt;
+pub mod irq;
+pub mod msr;
+pub mod mtrr;
+pub mod pic;
+pub mod port;
+pub mod smp;
+
+pub const MAX_CPUS: usize = 16;
+
+#[repr(C, packed)]
+pub struct DescriptorTablePointer {
+    pub limit: u16,
+    pub base: u32,
+}
+
+pub fn enable_interrupts() {
+    unsafe { asm!("sti", options(nomem, nostack)) };
+}
+
+pub fn disable_interrupts() {
+    unsafe { asm!("cli", options(nomem, nostack)) };
+}
+
+pub fn halt() {
+    unsafe { asm!("hlt", options(nomem, nostack, preserves_flags)) };
+}
+
+pub fn interrupts_enabled() -> bool {
+    let eflags: u32;
+    unsafe { asm!("pushfd", "pop {}", out(reg) eflags, options(nomem, preserves_flags)) };
+    eflags & (1 << 9) != 0
+}
+
+pub fn without_interrupts<T>(f: impl FnOnce() -> T) -> T {
+    let enabled = interrupts_enabled();
+    if enabled {
+        disable_interrupts();
+    }
+
+    let v = f();
+
+    if enabled {
+        enable_interrupts();
+    }
+
+    v
+}
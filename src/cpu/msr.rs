@@ -0,0 +1,24 @@
+use core::arch::asm;
+
+pub const IA32_APIC_BASE: u32 = 0x1B;
+pub const IA32_MTRRCAP: u32 = 0xFE;
+pub const IA32_MTRR_PHYSBASE0: u32 = 0x200;
+pub const IA32_MTRR_FIX64K_00000: u32 = 0x250;
+pub const IA32_MTRR_FIX16K_80000: u32 = 0x258;
+pub const IA32_MTRR_FIX4K_C0000: u32 = 0x268;
+pub const IA32_PAT: u32 = 0x277;
+pub const IA32_MTRR_DEF_TYPE: u32 = 0x2FF;
+pub const IA32_TSC_DEADLINE: u32 = 0x6E0;
+pub const IA32_EFER: u32 = 0xC000_0080;
+
+pub unsafe fn rdmsr(msr: u32) -> u64 {
+    let (high, low): (u32, u32);
+    asm!("rdmsr", in("ecx") msr, out("eax") low, out("edx") high, options(nomem, nostack, preserves_flags));
+    ((high as u64) << 32) | (low as u64)
+}
+
+pub unsafe fn wrmsr(msr: u32, value: u64) {
+    let low = value as u32;
+    let high = (value >> 32) as u32;
+    asm!("wrmsr", in("ecx") msr, in("eax") low, in("edx") high, options(nostack, preserves_flags));
+}
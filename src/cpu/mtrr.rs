@@ -0,0 +1,198 @@
+use super::msr::{
+    rdmsr, IA32_MTRRCAP, IA32_MTRR_DEF_TYPE, IA32_MTRR_FIX16K_80000, IA32_MTRR_FIX4K_C0000,
+    IA32_MTRR_FIX64K_00000, IA32_MTRR_PHYSBASE0,
+};
+
+const CAP_VARIABLE_COUNT_MASK: u64 = 0xFF;
+const CAP_FIXED: u64 = 1 << 8;
+const CAP_WRITE_COMBINING: u64 = 1 << 10;
+
+const DEF_TYPE_MASK: u64 = 0xFF;
+const DEF_TYPE_FIXED_ENABLE: u64 = 1 << 10;
+const DEF_TYPE_ENABLE: u64 = 1 << 11;
+
+const PHYSMASK_VALID: u64 = 1 << 11;
+const ADDRESS_MASK: u64 = !0xFFF;
+
+const MAX_VARIABLE: usize = 16;
+const FIXED_COUNT: usize = 11;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryType {
+    Uncacheable,
+    WriteCombining,
+    WriteThrough,
+    WriteProtected,
+    WriteBack,
+}
+
+impl MemoryType {
+    fn from_encoding(encoding: u8) -> Option<MemoryType> {
+        match encoding {
+            0x00 => Some(MemoryType::Uncacheable),
+            0x01 => Some(MemoryType::WriteCombining),
+            0x04 => Some(MemoryType::WriteThrough),
+            0x05 => Some(MemoryType::WriteProtected),
+            0x06 => Some(MemoryType::WriteBack),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct VariableRange {
+    base: u64,
+    mask: u64,
+    memory_type: MemoryType,
+}
+
+impl VariableRange {
+    // Ranges are naturally aligned powers of two, the lowest bit of the mask is the size
+    pub fn size(&self) -> u64 {
+        1 << self.mask.trailing_zeros()
+    }
+
+    pub fn contains(&self, address: u64) -> bool {
+        address & self.mask == self.base & self.mask
+    }
+}
+
+impl core::fmt::Debug for VariableRange {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "VariableRange {{ base: 0x{:09X}, size: 0x{:X}, memory_type: {:?} }}",
+            self.base,
+            self.size(),
+            self.memory_type
+        )
+    }
+}
+
+pub struct Mtrrs {
+    enabled: bool,
+    fixed_enabled: bool,
+    write_combining: bool,
+    default: MemoryType,
+    // One byte per fixed range, the first megabyte in 64, 16 and 4 KiB steps
+    fixed: [u64; FIXED_COUNT],
+    variable: [Option<VariableRange>; MAX_VARIABLE],
+}
+
+impl Mtrrs {
+    pub unsafe fn read() -> Mtrrs {
+        let cap = rdmsr(IA32_MTRRCAP);
+        let def_type = rdmsr(IA32_MTRR_DEF_TYPE);
+
+        let mut fixed = [0; FIXED_COUNT];
+        if cap & CAP_FIXED != 0 {
+            fixed[0] = rdmsr(IA32_MTRR_FIX64K_00000);
+            fixed[1] = rdmsr(IA32_MTRR_FIX16K_80000);
+            fixed[2] = rdmsr(IA32_MTRR_FIX16K_80000 + 1);
+            for (i, value) in fixed[3..].iter_mut().enumerate() {
+                *value = rdmsr(IA32_MTRR_FIX4K_C0000 + i as u32);
+            }
+        }
+
+        let count = ((cap & CAP_VARIABLE_COUNT_MASK) as usize).min(MAX_VARIABLE);
+        let mut variable = [None; MAX_VARIABLE];
+        for (i, range) in variable[..count].iter_mut().enumerate() {
+            let base = rdmsr(IA32_MTRR_PHYSBASE0 + 2 * i as u32);
+            let mask = rdmsr(IA32_MTRR_PHYSBASE0 + 2 * i as u32 + 1);
+            if mask & PHYSMASK_VALID == 0 {
+                continue;
+            }
+
+            *range = MemoryType::from_encoding(base as u8).map(|memory_type| VariableRange {
+                base: base & ADDRESS_MASK,
+                mask: mask & ADDRESS_MASK,
+                memory_type,
+            });
+        }
+
+        Mtrrs {
+            enabled: def_type & DEF_TYPE_ENABLE != 0,
+            fixed_enabled: cap & CAP_FIXED != 0 && def_type & DEF_TYPE_FIXED_ENABLE != 0,
+            write_combining: cap & CAP_WRITE_COMBINING != 0,
+            default: MemoryType::from_encoding((def_type & DEF_TYPE_MASK) as u8)
+                .unwrap_or(MemoryType::Uncacheable),
+            fixed,
+            variable,
+        }
+    }
+
+    pub fn supports_write_combining(&self) -> bool {
+        self.write_combining
+    }
+
+    pub fn variable_ranges(&self) -> impl Iterator<Item = &VariableRange> {
+        self.variable.iter().flatten()
+    }
+
+    fn fixed_type(&self, address: u64) -> Option<MemoryType> {
+        let (register, entry) = match address {
+            0x00000..=0x7FFFF => (0, address >> 16),
+            0x80000..=0xBFFFF => {
+                let index = (address - 0x80000) >> 14;
+                (1 + index / 8, index % 8)
+            }
+            _ => {
+                let index = (address - 0xC0000) >> 12;
+                (3 + index / 8, index % 8)
+            }
+        };
+
+        MemoryType::from_encoding((self.fixed[register as usize] >> (entry * 8)) as u8)
+    }
+
+    // The type the MTRRs give `address`, before the PAT gets a say
+    pub fn memory_type(&self, address: u64) -> MemoryType {
+        if !self.enabled {
+            return MemoryType::Uncacheable;
+        }
+
+        if self.fixed_enabled && address < 0x100000 {
+            return self.fixed_type(address).unwrap_or(MemoryType::Uncacheable);
+        }
+
+        let mut matches = self
+            .variable_ranges()
+            .filter(|range| range.contains(address));
+        let first = match matches.next() {
+            Some(range) => range.memory_type,
+            None => return self.default,
+        };
+
+        // Overlaps resolve to UC, or to WT for WT and WB, anything else is undefined
+        matches.fold(first, |current, range| match (current, range.memory_type) {
+            (MemoryType::Uncacheable, _) | (_, MemoryType::Uncacheable) => MemoryType::Uncacheable,
+            (MemoryType::WriteThrough, MemoryType::WriteBack)
+            | (MemoryType::WriteBack, MemoryType::WriteThrough) => MemoryType::WriteThrough,
+            (current, _) => current,
+        })
+    }
+}
+
+impl core::fmt::Debug for Mtrrs {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "Mtrrs {{ enabled: {}, fixed_enabled: {}, write_combining: {}, default: {:?}, variable: {} }}",
+            self.enabled,
+            self.fixed_enabled,
+            self.write_combining,
+            self.default,
+            self.variable_ranges().count()
+        )
+    }
+}
+
+static mut MTRRS: Option<Mtrrs> = None;
+
+pub fn init() -> &'static Mtrrs {
+    unsafe { MTRRS.insert(Mtrrs::read()) }
+}
+
+pub fn get() -> Option<&'static Mtrrs> {
+    unsafe { MTRRS.as_ref() }
+}
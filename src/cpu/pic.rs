@@ -0,0 +1,116 @@
+use super::port::{inb, io_wait, outb};
+
+const PIC1_COMMAND: u16 = 0x20;
+const PIC1_DATA: u16 = 0x21;
+const PIC2_COMMAND: u16 = 0xA0;
+const PIC2_DATA: u16 = 0xA1;
+
+const ICW1_ICW4: u8 = 0x01;
+const ICW1_INIT: u8 = 0x10;
+const ICW4_8086: u8 = 0x01;
+
+const OCW3_READ_ISR: u8 = 0x0B;
+const CMD_EOI: u8 = 0x20;
+
+pub const CASCADE_LINE: u8 = 2;
+
+pub struct ChainedPics {
+    master_offset: u8,
+    slave_offset: u8,
+}
+
+impl ChainedPics {
+    pub const fn new(master_offset: u8, slave_offset: u8) -> Self {
+        Self {
+            master_offset,
+            slave_offset,
+        }
+    }
+
+    pub unsafe fn initialize(&self) {
+        // Start the initialization sequence in cascade mode
+        outb(PIC1_COMMAND, ICW1_INIT | ICW1_ICW4);
+        io_wait();
+        outb(PIC2_COMMAND, ICW1_INIT | ICW1_ICW4);
+        io_wait();
+
+        // ICW2: vector offsets
+        outb(PIC1_DATA, self.master_offset);
+        io_wait();
+        outb(PIC2_DATA, self.slave_offset);
+        io_wait();
+
+        // ICW3: slave is attached to IRQ2 of the master, slave identity is 2
+        outb(PIC1_DATA, 1 << CASCADE_LINE);
+        io_wait();
+        outb(PIC2_DATA, CASCADE_LINE);
+        io_wait();
+
+        // ICW4: 8086 mode
+        outb(PIC1_DATA, ICW4_8086);
+        io_wait();
+        outb(PIC2_DATA, ICW4_8086);
+        io_wait();
+
+        // Mask everything except the cascade line until handlers are registered
+        self.set_masks(!(1 << CASCADE_LINE));
+    }
+
+    pub unsafe fn disable(&self) {
+        self.set_masks(0xFFFF);
+    }
+
+    pub unsafe fn masks(&self) -> u16 {
+        (inb(PIC1_DATA) as u16) | ((inb(PIC2_DATA) as u16) << 8)
+    }
+
+    pub unsafe fn set_masks(&self, masks: u16) {
+        outb(PIC1_DATA, (masks & 0xFF) as u8);
+        outb(PIC2_DATA, (masks >> 8) as u8);
+    }
+
+    pub unsafe fn mask(&self, line: u8) {
+        assert!(line < 16);
+
+        self.set_masks(self.masks() | (1 << line));
+    }
+
+    pub unsafe fn unmask(&self, line: u8) {
+        assert!(line < 16);
+
+        self.set_masks(self.masks() & !(1 << line));
+    }
+
+    pub unsafe fn in_service(&self) -> u16 {
+        outb(PIC1_COMMAND, OCW3_READ_ISR);
+        outb(PIC2_COMMAND, OCW3_READ_ISR);
+
+        (inb(PIC1_COMMAND) as u16) | ((inb(PIC2_COMMAND) as u16) << 8)
+    }
+
+    // IRQ 7 and 15 fire spuriously when a line deasserts before the CPU acknowledges it.
+    // Spurious IRQs must not be acknowledged on the PIC that raised them, but a spurious
+    // IRQ from the slave still needs an EOI on the master for the cascade line.
+    pub unsafe fn is_spurious(&self, line: u8) -> bool {
+        match line {
+            7 => self.in_service() & (1 << 7) == 0,
+            15 => {
+                let spurious = self.in_service() & (1 << 15) == 0;
+                if spurious {
+                    outb(PIC1_COMMAND, CMD_EOI);
+                }
+                spurious
+            }
+            _ => false,
+        }
+    }
+
+    pub unsafe fn end_of_interrupt(&self, line: u8) {
+        assert!(line < 16);
+
+        if line >= 8 {
+            outb(PIC2_COMMAND, CMD_EOI);
+        }
+        outb(PIC1_COMMAND, CMD_EOI);
+    }
+}
@@ -0,0 +1,16 @@
+use core::arch::asm;
+
+pub unsafe fn inb(port: u16) -> u8 {
+    let value: u8;
+    asm!("in al, dx", out("al") value, in("dx") port, options(nomem, nostack, preserves_flags));
+    value
+}
+
+pub unsafe fn outb(port: u16, value: u8) {
+    asm!("out dx, al", in("dx") port, in("al") value, options(nomem, nostack, preserves_flags));
+}
+
+// Port 0x80 is used for POST codes and is safe to write to as a short delay
+pub unsafe fn io_wait() {
+    outb(0x80, 0);
+}
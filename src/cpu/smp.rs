@@ -0,0 +1,216 @@
+use core::arch::asm;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use super::cpuid::Basic;
+use super::{apic, gdt, idt, MAX_CPUS};
+use crate::acpi::{self, Madt, MadtEntry};
+use crate::mem;
+use crate::misc::klog::kinfo;
+use crate::time;
+use crate::vga::{print, println};
+
+const TRAMPOLINE_BASE: usize = 0x8000;
+const AP_STACK_SIZE: usize = 64 * 1024;
+
+const MADT_CPU_ENABLED: u32 = 1 << 0;
+const MADT_CPU_ONLINE_CAPABLE: u32 = 1 << 1;
+
+const INIT_DELAY_NS: u64 = 10_000_000;
+const STARTUP_DELAY_NS: u64 = 200_000;
+const ONLINE_TIMEOUT_NS: u64 = 100_000_000;
+
+extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_data: u8;
+    static ap_trampoline_end: u8;
+}
+
+// Mirrors `ap_trampoline_data` in ap-trampoline.S
+#[repr(C)]
+struct TrampolineData {
+    stack: u32,
+    cr3: u32,
+    cr4: u32,
+    entry: u32,
+    cpu: u32,
+}
+
+#[repr(C, align(16))]
+struct ApStack([u8; AP_STACK_SIZE]);
+
+pub struct Cpu {
+    pub index: usize,
+    pub apic_id: u32,
+    pub basic: Basic,
+}
+
+const OFFLINE: Option<Cpu> = None;
+const EMPTY_AP_STACK: ApStack = ApStack([0; AP_STACK_SIZE]);
+
+static mut CPUS: [Option<Cpu>; MAX_CPUS] = [OFFLINE; MAX_CPUS];
+static mut AP_STACKS: [ApStack; MAX_CPUS] = [EMPTY_AP_STACK; MAX_CPUS];
+static AP_ONLINE: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn ap_main(cpu: usize) -> ! {
+    gdt::init(cpu);
+    idt::load();
+    if mem::paging::pat_enabled() {
+        mem::paging::load_pat();
+    }
+
+    let madt = acpi::get().and_then(|acpi| acpi.madt()).unwrap();
+    apic::init_ap(madt);
+
+    unsafe {
+        CPUS[cpu] = Some(Cpu {
+            index: cpu,
+            apic_id: apic::local().id(),
+            basic: Basic::read(),
+        });
+    }
+    AP_ONLINE.store(true, Ordering::Release);
+
+    // Nothing is routed to the APs yet
+    super::enable_interrupts();
+    loop {
+        super::halt();
+    }
+}
+
+fn trampoline_data() -> &'static mut TrampolineData {
+    unsafe {
+        let start = &ap_trampoline_start as *const u8 as usize;
+        let data = &ap_trampoline_data as *const u8 as usize;
+
+        &mut *((TRAMPOLINE_BASE + data - start) as *mut TrampolineData)
+    }
+}
+
+unsafe fn install_trampoline() {
+    let start = &ap_trampoline_start as *const u8;
+    let end = &ap_trampoline_end as *const u8;
+    let length = end as usize - start as usize;
+    assert!(length <= 0x1000, "AP trampoline doesn't fit in a page");
+
+    core::ptr::copy_nonoverlapping(start, TRAMPOLINE_BASE as *mut u8, length);
+
+    let cr0: u32;
+    asm!("mov {}, cr0", out(reg) cr0, options(nomem, nostack, preserves_flags));
+
+    let data = trampoline_data();
+    data.entry = ap_main as usize as u32;
+    data.cr3 = 0;
+    data.cr4 = 0;
+
+    if cr0 & (1 << 31) != 0 {
+        asm!("mov {}, cr3", out(reg) data.cr3, options(nomem, nostack, preserves_flags));
+        asm!("mov {}, cr4", out(reg) data.cr4, options(nomem, nostack, preserves_flags));
+    }
+}
+
+unsafe fn start_ap(cpu: usize, apic_id: u32) -> bool {
+    let data = trampoline_data();
+    data.cpu = cpu as u32;
+    data.stack = AP_STACKS[cpu].0.as_ptr() as u32 + AP_STACK_SIZE as u32;
+
+    AP_ONLINE.store(false, Ordering::Release);
+
+    let local = apic::local();
+    let page = (TRAMPOLINE_BASE >> 12) as u8;
+
+    // INIT-SIPI-SIPI, the second SIPI is ignored by an AP that already started
+    local.send_init(apic_id);
+    time::delay_ns(INIT_DELAY_NS);
+    local.send_startup(apic_id, page);
+    time::delay_ns(STARTUP_DELAY_NS);
+    local.send_startup(apic_id, page);
+
+    let deadline = time::now_ns() + ONLINE_TIMEOUT_NS;
+    while !AP_ONLINE.load(Ordering::Acquire) {
+        if time::now_ns() >= deadline {
+            return false;
+        }
+
+        core::hint::spin_loop();
+    }
+
+    true
+}
+
+fn application_processors(madt: &Madt) -> impl Iterator<Item = u32> + '_ {
+    let bsp = apic::local().id();
+    let x2apic = apic::local().mode() == apic::Mode::X2Apic;
+
+    madt.entries()
+        .filter_map(|entry| match entry {
+            MadtEntry::LocalApic { apic_id, flags, .. } => Some((apic_id as u32, flags)),
+            MadtEntry::LocalX2Apic {
+                x2apic_id, flags, ..
+            } => Some((x2apic_id, flags)),
+            _ => None,
+        })
+        .filter(|(_, flags)| flags & (MADT_CPU_ENABLED | MADT_CPU_ONLINE_CAPABLE) != 0)
+        .map(|(apic_id, _)| apic_id)
+        // xAPIC IPIs can only address 8-bit APIC IDs
+        .filter(move |&apic_id| apic_id != bsp && (x2apic || apic_id <= 0xFF))
+}
+
+pub fn init(madt: Option<&Madt>) {
+    unsafe {
+        CPUS[0] = Some(Cpu {
+            index: 0,
+            apic_id: if apic::is_enabled() {
+                apic::local().id()
+            } else {
+                0
+            },
+            basic: Basic::read(),
+        });
+    }
+
+    let madt = match madt {
+        Some(madt) if apic::is_enabled() => madt,
+        _ => return,
+    };
+
+    unsafe { install_trampoline() };
+
+    let mut next = 1;
+    for apic_id in application_processors(madt) {
+        if next == MAX_CPUS {
+            kinfo!(
+                "Ignoring CPUs beyond {}, starting with APIC {}",
+                MAX_CPUS,
+                apic_id
+            );
+            break;
+        }
+
+        // Don't hand a late AP's stack to the next one
+        if !unsafe { start_ap(next, apic_id) } {
+            kinfo!("CPU with APIC {} didn't come online", apic_id);
+        }
+        next += 1;
+    }
+}
+
+pub fn cpus() -> impl Iterator<Item = &'static Cpu> {
+    unsafe { CPUS.iter().flatten() }
+}
+
+// Index of the CPU this runs on, which has to be online already
+pub fn current_index() -> usize {
+    if !apic::is_enabled() {
+        return 0;
+    }
+
+    let apic_id = apic::local().id();
+    cpus()
+        .find(|cpu| cpu.apic_id == apic_id)
+        .map(|cpu| cpu.index)
+        .unwrap_or_else(|| panic!("CPU with APIC {} is not online", apic_id))
+}
+
+pub fn online_count() -> usize {
+    cpus().count()
+}
@@ -1,14 +1,18 @@
 #![no_std]
 #![no_main]
 #![feature(abi_x86_interrupt)]
+#![feature(alloc_error_handler)]
 
+extern crate alloc;
+
+mod acpi;
 mod cpu;
 mod mem;
 mod misc;
+mod time;
 mod vga;
 
 use crate::misc::banner;
-use core::arch::asm;
 use core::panic::PanicInfo;
 
 use misc::klog::{kdbg, kinfo};
@@ -28,23 +32,69 @@ fn panic(info: &PanicInfo) -> ! {
     println!("================================================================================");
     println!("Clearing interrupts and halting CPU...");
 
-    unsafe { asm!("hlt") };
+    cpu::disable_interrupts();
+    cpu::halt();
 
     println!("CPU halted, this should never happen!");
 
     loop {}
 }
 
+// Arms a short one-shot timer and waits for it to fire
+fn test_oneshot(name: &str, set: fn(u64, fn()), cancel: fn()) {
+    use core::sync::atomic::{AtomicBool, Ordering};
+
+    static FIRED: AtomicBool = AtomicBool::new(false);
+
+    FIRED.store(false, Ordering::Release);
+    let start = time::now_ns();
+    set(100_000, || FIRED.store(true, Ordering::Release));
+    while !FIRED.load(Ordering::Acquire) && time::now_ns() - start < 10_000_000 {
+        core::hint::spin_loop();
+    }
+
+    if FIRED.load(Ordering::Acquire) {
+        kdbg!(
+            "{} one-shot fired after {} ns",
+            name,
+            time::now_ns() - start
+        );
+    } else {
+        cancel();
+        kinfo!("{} one-shot didn't fire within 10 ms", name);
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn _rust_main(mb_magic: usize, mb_addr: usize) {
     vga::clear_screen();
     banner::print_banner();
 
+    cpu::gdt::init(0);
+    kdbg!("Loaded GDT and TSS");
+
+    cpu::idt::init();
+    kdbg!("Loaded IDT");
+
+    cpu::irq::init();
+    cpu::enable_interrupts();
+    kdbg!("Remapped PIC and enabled interrupts");
+
     if mb_magic != 0x36d76289 {
         panic!("Invalid multiboot magic, 0x{:08X} != 0x36d76289", mb_magic);
     }
 
     kinfo!("Received multiboot2 information at: {:?}", mb_addr);
+
+    // Until the kernel is remapped only the boot window reaches it, the total size comes first
+    let mb_start = mem::kernel_to_physical(mb_addr);
+    let in_window = |end: usize| end <= mem::BOOT_WINDOW_SIZE;
+    assert!(
+        in_window(mb_start + 8)
+            && in_window(mb_start + unsafe { *(mb_addr as *const u32) } as usize),
+        "Multiboot information at 0x{:08X} is outside the boot window",
+        mb_start
+    );
     let boot_info_ptr = (mb_addr as *const u8).cast();
     let boot_info = unsafe { BootInformation::load(boot_info_ptr).unwrap() };
 
@@ -52,12 +102,8 @@ pub extern "C" fn _rust_main(mb_magic: usize, mb_addr: usize) {
     {
         let mut available = 0;
 
-        kdbg!("Available memory areas:");
-        for area in memory_map
-            .memory_areas()
-            .iter()
-            .filter(|a| a.typ() == multiboot2::MemoryAreaType::Available)
-        {
+        kdbg!("Memory areas:");
+        for area in memory_map.memory_areas() {
             kdbg!(
                 "  0x{:08X} - 0x{:08X} ({} bytes, {:?})",
                 area.start_address(),
@@ -66,9 +112,13 @@ pub extern "C" fn _rust_main(mb_magic: usize, mb_addr: usize) {
                 area.typ(),
             );
 
-            available += area.size();
+            if area.typ() == multiboot2::MemoryAreaType::Available {
+                available += area.size();
+            }
         }
         kinfo!("Available memory: {} bytes", available);
+
+        mem::zone::init(memory_map.memory_areas());
     }
 
     let (kernel, multiboot) = {
@@ -77,29 +127,33 @@ pub extern "C" fn _rust_main(mb_magic: usize, mb_addr: usize) {
         kdbg!("ELF sections:");
         for section in elf_sections.clone() {
             kdbg!(
-                "  0x{:08X} - 0x{:08X} ({} bytes, {:?})",
+                "  0x{:08X} - 0x{:08X} at 0x{:08X} ({} bytes, {:?})",
                 section.start_address(),
                 section.end_address(),
+                mem::kernel_to_physical(section.start_address() as usize),
                 section.size(),
                 section.flags(),
             );
         }
 
-        let kernel_start = elf_sections
-            .clone()
-            .map(|s| s.start_address())
-            .min()
-            .unwrap();
-        let kernel_end = elf_sections.map(|s| s.end_address()).max().unwrap();
+        let loaded = elf_sections.filter(|s| s.is_allocated()).map(|s| {
+            (
+                mem::kernel_to_physical(s.start_address() as usize),
+                mem::kernel_to_physical(s.end_address() as usize),
+            )
+        });
+        let kernel_start = loaded.clone().map(|(start, _)| start).min().unwrap();
+        let kernel_end = loaded.map(|(_, end)| end).max().unwrap();
 
-        let mb_start = mb_addr;
-        let mb_end = mb_addr + boot_info.total_size();
+        let mb_start = mem::kernel_to_physical(mb_addr);
+        let mb_end = mb_start + boot_info.total_size();
 
         kinfo!(
-            "Kernel loaded at 0x{:08X} - 0x{:08X} ({} bytes)",
+            "Kernel loaded at 0x{:08X} - 0x{:08X} ({} bytes), linked at 0x{:08X}",
             kernel_start,
             kernel_end,
-            kernel_end - kernel_start
+            kernel_end - kernel_start,
+            mem::KERNEL_OFFSET
         );
         kinfo!(
             "Multiboot2 information at 0x{:08X} - 0x{:08X} ({} bytes)",
@@ -108,28 +162,25 @@ pub extern "C" fn _rust_main(mb_magic: usize, mb_addr: usize) {
             mb_end - mb_start
         );
 
-        (
-            (kernel_start as usize, kernel_end as usize),
-            (mb_start, mb_end),
-        )
+        ((kernel_start, kernel_end), (mb_start, mb_end))
     };
 
-    {
+    let (basic, extended) = {
         let basic = cpu::cpuid::Basic::read();
         let extended = cpu::cpuid::Extended::read();
 
         kdbg!("CPU Information:");
         kdbg!("  Manufacturer: {}", basic.manufacturer);
 
-        if let Some(brand) = extended.brand {
+        if let Some(brand) = &extended.brand {
             kdbg!("  Brand: {}", brand);
         }
 
-        if let Some(vendor) = extended.vendor {
+        if let Some(vendor) = &extended.vendor {
             kdbg!("  Vendor: {}", vendor);
         }
 
-        if let Some(info) = basic.basic_info {
+        if let Some(info) = &basic.basic_info {
             kdbg!("  Info:");
             kdbg!("    Type: {:?}", info.type_);
             kdbg!(
@@ -146,7 +197,7 @@ pub extern "C" fn _rust_main(mb_magic: usize, mb_addr: usize) {
         }
 
         print!("[DBG]    Capabilities: ");
-        if let Some(info) = basic.info_and_bits {
+        if let Some(info) = &basic.info_and_bits {
             for (bit, ..) in info.edx.iter_names() {
                 print!("{} ", bit);
             }
@@ -154,7 +205,7 @@ pub extern "C" fn _rust_main(mb_magic: usize, mb_addr: usize) {
                 print!("{} ", bit);
             }
         }
-        if let Some(info) = extended.info_and_bits {
+        if let Some(info) = &extended.info_and_bits {
             for (bit, ..) in info.edx.iter_names() {
                 print!("{} ", bit);
             }
@@ -167,11 +218,495 @@ pub extern "C" fn _rust_main(mb_magic: usize, mb_addr: usize) {
         if let Some(svm_revision) = extended.svm_revision {
             kdbg!("  SVM Revision: {}", svm_revision);
         }
+
+        (basic, extended)
+    };
+
+    let acpi = match acpi::init(&boot_info) {
+        Ok(acpi) => {
+            let rsdp = acpi.rsdp();
+
+            kinfo!(
+                "Found ACPI {} RSDP ({:?}, revision {})",
+                rsdp.oem_id(),
+                acpi.source(),
+                rsdp.revision()
+            );
+            kdbg!("ACPI tables:");
+            kdbg!("  {:?}", acpi.root());
+            for table in acpi.tables() {
+                kdbg!("  {:?}", table);
+            }
+
+            if let Some(fadt) = acpi.fadt() {
+                kdbg!("  {:?}", fadt);
+                time::rtc::set_century_register(fadt.century_register());
+            }
+            if let Some(hpet) = acpi.hpet() {
+                kdbg!("  {:?}", hpet);
+            }
+            if let Some(mcfg) = acpi.mcfg() {
+                for entry in mcfg.entries() {
+                    kdbg!("  {:?}", entry);
+                }
+            }
+
+            Some(acpi)
+        }
+        Err(err) => {
+            kinfo!("ACPI not available: {:?}", err);
+            None
+        }
+    };
+
+    {
+        use cpu::cpuid::{BasicInfoAndBitsECX, BasicInfoAndBitsEDX};
+
+        let (apic, x2apic) = basic.info_and_bits.as_ref().map_or((false, false), |info| {
+            (
+                info.edx.contains(BasicInfoAndBitsEDX::apic),
+                info.ecx.contains(BasicInfoAndBitsECX::x2apic),
+            )
+        });
+
+        match acpi.and_then(|acpi| acpi.madt()) {
+            Some(madt) if apic => {
+                cpu::apic::init(madt, x2apic);
+
+                let local = cpu::apic::local();
+                kinfo!(
+                    "Enabled local APIC {} (version 0x{:02X}, {:?}), routing IRQs through the I/O APIC",
+                    local.id(),
+                    local.version(),
+                    local.mode()
+                );
+                for io_apic in cpu::apic::io_apics() {
+                    kdbg!("  {:?}", io_apic);
+                }
+            }
+            _ => {
+                kinfo!("APIC not available, using the legacy PIC");
+            }
+        }
+    }
+
+    let hpet = acpi.and_then(|acpi| acpi.hpet()).and_then(time::hpet::init);
+    if let Some(hpet) = hpet {
+        kdbg!("Initialized {:?}", hpet);
+    }
+
+    // `tick=pit|rtc|hpet` on the kernel command line overrides the default tick source
+    let requested = boot_info
+        .command_line_tag()
+        .and_then(|tag| tag.cmdline().ok())
+        .and_then(|cmdline| {
+            cmdline
+                .split_whitespace()
+                .find_map(|arg| arg.strip_prefix("tick="))
+        })
+        .and_then(|name| match name {
+            "pit" => Some(time::TickSource::Pit),
+            "rtc" => Some(time::TickSource::Rtc),
+            "hpet" => Some(time::TickSource::Hpet),
+            _ => None,
+        });
+    let hpet_ticks = hpet.map_or(false, |hpet| hpet.legacy_replacement_capable());
+    let tick_source = match requested {
+        Some(time::TickSource::Hpet) if !hpet_ticks => {
+            kinfo!("HPET can't replace the PIT, ignoring tick=hpet");
+            time::TickSource::Pit
+        }
+        Some(source) => source,
+        None if hpet_ticks => time::TickSource::Hpet,
+        None => time::TickSource::Pit,
+    };
+    time::init(tick_source, time::DEFAULT_FREQUENCY);
+    misc::klog::set_show_uptime(true);
+    kinfo!(
+        "Started {:?} tick clock at {} Hz",
+        time::tick_source(),
+        time::frequency()
+    );
+    kinfo!("Wall clock: {}", time::wall_clock());
+
+    {
+        use cpu::cpuid::{BasicInfoAndBitsECX, BasicInfoAndBitsEDX};
+
+        let (tsc, tsc_deadline) = basic.info_and_bits.as_ref().map_or((false, false), |info| {
+            (
+                info.edx.contains(BasicInfoAndBitsEDX::tsc),
+                info.ecx.contains(BasicInfoAndBitsECX::tsc_deadline),
+            )
+        });
+
+        if tsc {
+            let calibration = time::tsc::calibrate(basic.tsc_info.as_ref());
+            time::set_clocksource(time::Clocksource::Tsc);
+            match calibration {
+                time::tsc::Calibration::Cpuid => kinfo!(
+                    "Got TSC frequency of {} kHz from CPUID, using it as clocksource",
+                    time::tsc::frequency() / 1000
+                ),
+                time::tsc::Calibration::TickSource(source) => kinfo!(
+                    "Calibrated TSC at {} kHz against the {:?} tick, using it as clocksource",
+                    time::tsc::frequency() / 1000,
+                    source
+                ),
+            }
+
+            if tsc_deadline && cpu::apic::is_enabled() {
+                time::tsc::enable_deadline_timer();
+                kdbg!("Enabled TSC-deadline timer");
+            }
+
+            if time::tsc::has_deadline_timer() {
+                test_oneshot(
+                    "TSC-deadline",
+                    time::tsc::set_oneshot,
+                    time::tsc::cancel_oneshot,
+                );
+            }
+        } else if hpet.is_some() {
+            time::set_clocksource(time::Clocksource::Hpet);
+            kinfo!("Using HPET as clocksource");
+        }
+    }
+
+    if hpet.is_some() && cpu::apic::is_enabled() {
+        if let Some((comparator, gsi)) = time::hpet::enable_oneshot_timer() {
+            kdbg!("Routed HPET comparator {} to GSI {}", comparator, gsi);
+            test_oneshot("HPET", time::hpet::set_oneshot, time::hpet::cancel_oneshot);
+        }
+    }
+
+    {
+        use cpu::cpuid::BasicInfoAndBitsEDX;
+
+        let (pat, mtrr) = basic.info_and_bits.as_ref().map_or((false, false), |info| {
+            (
+                info.edx.contains(BasicInfoAndBitsEDX::pat),
+                info.edx.contains(BasicInfoAndBitsEDX::mtrr),
+            )
+        });
+
+        if mtrr {
+            let mtrrs = cpu::mtrr::init();
+            kinfo!("{:?}", mtrrs);
+            for range in mtrrs.variable_ranges() {
+                kdbg!("  {:?}", range);
+            }
+            kdbg!(
+                "  VGA buffer is {:?}",
+                mtrrs.memory_type(vga::BUFFER_ADDRESS as u64)
+            );
+        }
+
+        if pat {
+            mem::paging::init_pat();
+            kinfo!("Programmed the PAT: {:?}", mem::paging::read_pat());
+        }
+    }
+
+    cpu::smp::init(acpi.and_then(|acpi| acpi.madt()));
+    kinfo!("{} CPUs online", cpu::smp::online_count());
+    for cpu in cpu::smp::cpus() {
+        match &cpu.basic.basic_info {
+            Some(info) => kdbg!(
+                "  CPU {}: APIC {}, {}, family {:01X}h, model {:01X}h, stepping {}",
+                cpu.index,
+                cpu.apic_id,
+                cpu.basic.manufacturer,
+                info.family,
+                info.model,
+                info.stepping
+            ),
+            None => kdbg!(
+                "  CPU {}: APIC {}, {}",
+                cpu.index,
+                cpu.apic_id,
+                cpu.basic.manufacturer
+            ),
+        }
+    }
+
+    let start = time::now_ns();
+    let mut bootstrap_allocator =
+        mem::BitmapFrameAllocator::new(kernel, multiboot, memory_map.memory_areas());
+    kdbg!(
+        "Bitmap frame allocator found {} free frames out of {}",
+        bootstrap_allocator.free_frames(),
+        bootstrap_allocator.total_frames()
+    );
+
+    let mut frame_allocator = mem::BuddyAllocator::new(&mut bootstrap_allocator);
+    kdbg!(
+        "Initialized frame allocator in {} ns",
+        time::now_ns() - start
+    );
+    kinfo!("{:?}", frame_allocator);
+
+    {
+        use mem::FrameAllocator;
+
+        let frame = frame_allocator.allocate().expect("Out of physical memory");
+        kdbg!("Allocated frame {}", frame);
+        frame_allocator.deallocate(frame);
+
+        let block = frame_allocator
+            .allocate_order(4)
+            .expect("Out of physical memory");
+        kdbg!("Allocated 16 contiguous frames starting at {}", block);
+        frame_allocator.deallocate_order(block, 4);
+
+        let dma = frame_allocator
+            .allocate_in(mem::zone::Zone::Dma)
+            .expect("Out of ISA DMA memory");
+        kdbg!("Allocated frame {} for ISA DMA", dma);
+        frame_allocator.deallocate(dma);
+    }
+
+    let mut kernel_directory = {
+        use cpu::cpuid::{BasicInfoAndBitsEDX, ExtendedInfoAndBitsEDX};
+        use mem::paging::{CacheType, EntryFlags, Mode};
+
+        let pae = basic
+            .info_and_bits
+            .as_ref()
+            .map_or(false, |info| info.edx.contains(BasicInfoAndBitsEDX::pae));
+        let nx = extended
+            .info_and_bits
+            .as_ref()
+            .map_or(false, |info| info.edx.contains(ExtendedInfoAndBitsEDX::nx));
+        let pse = basic
+            .info_and_bits
+            .as_ref()
+            .map_or(false, |info| info.edx.contains(BasicInfoAndBitsEDX::pse));
+        let pse36 = basic
+            .info_and_bits
+            .as_ref()
+            .map_or(false, |info| info.edx.contains(BasicInfoAndBitsEDX::pse36));
+        let mode = if pae {
+            Mode::Pae { nx }
+        } else {
+            Mode::Legacy { pse, pse36 }
+        };
+
+        let device =
+            (EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE).with_cache(CacheType::Uncacheable);
+
+        let local_apic = match cpu::apic::is_enabled().then(|| cpu::apic::local().mode()) {
+            Some(cpu::apic::Mode::XApic { base }) => Some(base..base + 0x1000),
+            _ => None,
+        };
+        let hpet = hpet.map(|hpet| hpet.base()..hpet.base() + 0x400);
+
+        let mmio = local_apic
+            .into_iter()
+            .chain(hpet)
+            .map(|range| (range, device));
+
+        mem::remap_kernel(&boot_info, mode, mmio, &mut frame_allocator)
+    };
+    kinfo!(
+        "Remapped the kernel and enabled {:?} paging, root table at {}",
+        kernel_directory.mode(),
+        kernel_directory.root_frame()
+    );
+    kdbg!("{:?}", frame_allocator);
+
+    mem::heap::init(&mut kernel_directory, &mut frame_allocator);
+    kinfo!(
+        "Mapped {} KiB kernel heap at 0x{:08X}",
+        mem::heap::HEAP_SIZE / 1024,
+        mem::heap::HEAP_START
+    );
+
+    {
+        let squares = (0..16u32).map(|n| n * n).collect::<alloc::vec::Vec<_>>();
+        let message = alloc::format!("{} squares, the last being {}", squares.len(), squares[15]);
+        kdbg!(
+            "Heap works: {} ({} bytes used, {} free)",
+            message,
+            mem::heap::used(),
+            mem::heap::free()
+        );
+    }
+
+    mem::init(kernel_directory, frame_allocator);
+
+    if let Some(acpi) = acpi {
+        acpi::map_tables();
+        kdbg!(
+            "Mapped ACPI root table at 0x{:08X}",
+            acpi.root() as *const _ as usize
+        );
+    }
+
+    if cpu::apic::is_enabled() {
+        cpu::apic::map_io_apics();
+        for io_apic in cpu::apic::io_apics() {
+            kdbg!(
+                "Mapped I/O APIC {} at 0x{:08X}",
+                io_apic.id(),
+                io_apic.base()
+            );
+        }
+    }
+
+    {
+        let stats = mem::stats();
+
+        kinfo!("Physical memory zones:");
+        for zone in mem::zone::Zone::ALL {
+            kdbg!("  {:?}: {:?}", zone, stats.zone(zone));
+        }
+
+        kinfo!("Physical memory by area type:");
+        for area in stats.areas.iter().flatten() {
+            kdbg!("  {:?}", area);
+        }
+
+        if let Some(available) = stats.area(multiboot2::MemoryAreaType::Available) {
+            kinfo!(
+                "{} KiB of {} KiB available memory free",
+                available.frames.free * mem::PAGE_SIZE / 1024,
+                available.frames.total * mem::PAGE_SIZE / 1024
+            );
+        }
+    }
+
+    {
+        use mem::paging::{EntryFlags, Mapper, Page};
+        use mem::{vmm, PAGE_SIZE};
+
+        let free = || mem::with_frame_allocator(|allocator| allocator.free_frames());
+
+        let before = free();
+        let buffer = vmm::allocate(
+            "demand paging example",
+            4 * PAGE_SIZE,
+            EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE,
+        )
+        .expect("Out of kernel virtual memory");
+        let value = unsafe {
+            (buffer as *mut u32).write_volatile(0xDEADBEEF);
+            ((buffer + 4 * PAGE_SIZE - 4) as *const u32).read_volatile()
+        };
+        kdbg!(
+            "Touched both ends of a lazily mapped region at 0x{:08X}, read {}, used {} frames",
+            buffer,
+            value,
+            before - free()
+        );
+
+        vmm::protect(buffer, PAGE_SIZE, EntryFlags::NO_EXECUTE);
+        let writable = mem::with_kernel_directory(|directory, _| {
+            directory
+                .flags(Page::containing_address(buffer))
+                .map(|flags| flags.contains(EntryFlags::WRITABLE))
+        });
+        kdbg!("Write protected the first page, writable: {:?}", writable);
+
+        static TEXT: &[u8] = b"File backed pages are read in on first access";
+        let file = vmm::map_file("file example", TEXT, EntryFlags::NO_EXECUTE)
+            .expect("Out of kernel virtual memory");
+        let text = unsafe { core::slice::from_raw_parts(file as *const u8, TEXT.len()) };
+        kdbg!(
+            "Read {:?} from a file mapping at 0x{:08X}",
+            core::str::from_utf8(text).unwrap_or("?"),
+            file
+        );
+        vmm::unmap(file, TEXT.len());
+
+        let vga = vmm::map_framebuffer(vga::BUFFER_ADDRESS, vga::BUFFER_WIDTH * 2);
+        let first = unsafe { (vga as *const u8).read_volatile() };
+        let cache = mem::with_kernel_directory(|directory, _| {
+            directory
+                .flags(Page::containing_address(vga))
+                .map(|flags| flags.cache_type())
+        });
+        kdbg!(
+            "VGA buffer mapped again at 0x{:08X} ({:?}), first character {:?}",
+            vga,
+            cache,
+            first as char
+        );
+
+        // Leaves two regions behind, one on each side of the hole
+        vmm::unmap(buffer + PAGE_SIZE, 2 * PAGE_SIZE);
+        vmm::dump();
+
+        vmm::unmap(vga, vga::BUFFER_WIDTH * 2);
+        vmm::unmap(buffer, 4 * PAGE_SIZE);
     }
 
-    let frame_allocator =
-        mem::AreaFrameAllocator::new(kernel, multiboot, memory_map.memory_areas());
-    kdbg!("Initialized frame allocator");
+    {
+        use mem::address_space::{Backing, Region, RegionKind};
+        use mem::paging::{EntryFlags, Mapper};
+        use mem::PAGE_SIZE;
+
+        let address = mem::with_kernel_address_space(|address_space, _| {
+            let address = address_space
+                .find_free(0x4000_0000..mem::KERNEL_OFFSET, PAGE_SIZE, PAGE_SIZE)
+                .expect("No room below the kernel");
+            address_space.add_region(Region::new(
+                "copy-on-write example",
+                address..address + PAGE_SIZE,
+                RegionKind::Mapped(Backing::Anonymous),
+                EntryFlags::WRITABLE | EntryFlags::USER | EntryFlags::NO_EXECUTE,
+            ));
+            address
+        });
+
+        let value = address as *mut u32;
+        unsafe { value.write_volatile(1) };
+        let before = mem::with_kernel_directory(|directory, _| directory.translate(address));
+
+        let mut clone = mem::with_kernel_address_space(|address_space, allocator| {
+            address_space.clone_cow(allocator)
+        });
+        unsafe { value.write_volatile(2) };
+
+        // The clone is the last owner of the old frame now, its write fault just makes the page
+        // writable again
+        let cloned = unsafe {
+            mem::switch_to(&mut clone);
+            let cloned = value.read_volatile();
+            value.write_volatile(3);
+            mem::switch_to_kernel();
+            cloned
+        };
+
+        mem::with_kernel_address_space(move |address_space, allocator| {
+            kdbg!(
+                "Copied a page on write, 0x{:08X} moved from {:08X?} to {:08X?}, the clone kept {:08X?} holding {}",
+                address,
+                before,
+                address_space.directory().translate(address),
+                clone.directory().translate(address),
+                cloned
+            );
+
+            clone.free(allocator);
+            address_space.unmap(address..address + PAGE_SIZE, allocator);
+        });
+    }
+
+    {
+        let cache = mem::slab::create(
+            "example",
+            48,
+            8,
+            Some(|object| unsafe { object.write_bytes(0, 48) }),
+        );
+
+        let objects = (0..4)
+            .map(|_| cache.allocate().expect("Slab cache exhausted"))
+            .collect::<alloc::vec::Vec<_>>();
+        unsafe { cache.free(objects[1]) };
+
+        mem::slab::dump();
+    }
 
     vga::restore_colors(|| {
         print!("[INFO] Color test: ");
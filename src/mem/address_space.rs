@@ -0,0 +1,502 @@
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use super::paging::{Directory, EntryFlags, Mapper, Page};
+use super::{refcount, Frame, FrameAllocator, KERNEL_OFFSET, PAGE_SIZE};
+use crate::cpu::idt::PageFaultErrorCode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backing {
+    // Zeroed frames, allocated and mapped on first access
+    Anonymous,
+    // A fixed physical range, usually device memory, `start` is where the region begins
+    Physical { start: usize },
+    // Pages are filled from `data` on first access, starting at `offset` for the region's first
+    // page, whatever lies past the end of `data` reads as zeroes
+    File { data: &'static [u8], offset: usize },
+}
+
+impl Backing {
+    // The backing of the part of a region that starts `by` bytes in
+    fn advance(self, by: usize) -> Self {
+        match self {
+            Backing::Anonymous => Backing::Anonymous,
+            Backing::Physical { start } => Backing::Physical { start: start + by },
+            Backing::File { data, offset } => Backing::File {
+                data,
+                offset: offset + by,
+            },
+        }
+    }
+
+    // Whether a region backed by `self` followed by `by` bytes can absorb one backed by `next`
+    fn continues_with(self, by: usize, next: Backing) -> bool {
+        match (self.advance(by), next) {
+            (Backing::Anonymous, Backing::Anonymous) => true,
+            (Backing::Physical { start: a }, Backing::Physical { start: b }) => a == b,
+            (
+                Backing::File {
+                    data: a,
+                    offset: a_offset,
+                },
+                Backing::File {
+                    data: b,
+                    offset: b_offset,
+                },
+            ) => a.as_ptr() == b.as_ptr() && a.len() == b.len() && a_offset == b_offset,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    // Claimed so nothing else ends up there, but nothing is mapped on demand
+    Reserved,
+    // Never mapped, touching it means a stack ran over its end
+    Guard,
+    Mapped(Backing),
+}
+
+#[derive(Clone)]
+pub struct Region {
+    name: &'static str,
+    range: Range<usize>,
+    kind: RegionKind,
+    flags: EntryFlags,
+}
+
+impl Region {
+    pub fn new(
+        name: &'static str,
+        range: Range<usize>,
+        kind: RegionKind,
+        flags: EntryFlags,
+    ) -> Self {
+        assert!(
+            range.start % PAGE_SIZE == 0 && range.end % PAGE_SIZE == 0,
+            "Region {} is not page aligned",
+            name
+        );
+        assert!(!range.is_empty(), "Region {} is empty", name);
+
+        Self {
+            name,
+            range,
+            kind,
+            flags,
+        }
+    }
+
+    pub fn reserved(name: &'static str, range: Range<usize>) -> Self {
+        Self::new(name, range, RegionKind::Reserved, EntryFlags::empty())
+    }
+
+    pub fn guard(name: &'static str, range: Range<usize>) -> Self {
+        Self::new(name, range, RegionKind::Guard, EntryFlags::empty())
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn kind(&self) -> RegionKind {
+        self.kind
+    }
+
+    pub fn contains(&self, address: usize) -> bool {
+        self.range.contains(&address)
+    }
+
+    // Whether an access described by `error` to a page that isn't mapped yet is allowed
+    fn allows(&self, error: PageFaultErrorCode) -> bool {
+        (!error.contains(PageFaultErrorCode::WRITE) || self.flags.contains(EntryFlags::WRITABLE))
+            && (!error.contains(PageFaultErrorCode::USER) || self.flags.contains(EntryFlags::USER))
+            && (!error.contains(PageFaultErrorCode::INSTRUCTION_FETCH)
+                || !self.flags.contains(EntryFlags::NO_EXECUTE))
+    }
+
+    // The part of the region inside `range`, which has to overlap it
+    fn slice(&self, range: Range<usize>) -> Region {
+        let start = range.start.max(self.range.start);
+        let end = range.end.min(self.range.end);
+
+        let kind = match self.kind {
+            RegionKind::Mapped(backing) => {
+                RegionKind::Mapped(backing.advance(start - self.range.start))
+            }
+            kind => kind,
+        };
+
+        Region {
+            name: self.name,
+            range: start..end,
+            kind,
+            flags: self.flags,
+        }
+    }
+
+    // Whether the frames mapped in the region belong to it, rather than being device memory
+    fn owns_frames(&self) -> bool {
+        matches!(
+            self.kind,
+            RegionKind::Mapped(Backing::Anonymous | Backing::File { .. })
+        )
+    }
+
+    fn can_merge(&self, next: &Region) -> bool {
+        if self.range.end != next.range.start || self.name != next.name || self.flags != next.flags
+        {
+            return false;
+        }
+
+        match (self.kind, next.kind) {
+            (RegionKind::Mapped(a), RegionKind::Mapped(b)) => a.continues_with(self.range.len(), b),
+            (a, b) => a == b,
+        }
+    }
+}
+
+impl core::fmt::Debug for Region {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "Region {{ name: {}, range: 0x{:08X} - 0x{:08X}, kind: ",
+            self.name, self.range.start, self.range.end
+        )?;
+
+        match self.kind {
+            RegionKind::Mapped(Backing::Physical { start }) => {
+                write!(f, "Physical(0x{:08X})", start)?
+            }
+            RegionKind::Mapped(Backing::File { data, offset }) => write!(
+                f,
+                "File({} bytes at {:p}, offset {})",
+                data.len(),
+                data,
+                offset
+            )?,
+            RegionKind::Mapped(Backing::Anonymous) => write!(f, "Anonymous")?,
+            kind => write!(f, "{:?}", kind)?,
+        }
+
+        write!(f, ", flags: {:?} }}", self.flags)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageFaultError {
+    // The address doesn't belong to any region
+    NoRegion,
+    Guard(&'static str),
+    // The page is mapped, or the region doesn't allow the access or has nothing to map
+    Protection(&'static str),
+    OutOfMemory(&'static str),
+}
+
+// A page directory together with the regions that say which parts of it are in use and what
+// backs them
+pub struct AddressSpace {
+    directory: Directory,
+    // Sorted by start address, never overlapping
+    regions: Vec<Region>,
+}
+
+impl AddressSpace {
+    pub fn new(directory: Directory) -> Self {
+        Self {
+            directory,
+            regions: Vec::new(),
+        }
+    }
+
+    pub fn directory(&self) -> &Directory {
+        &self.directory
+    }
+
+    pub fn directory_mut(&mut self) -> &mut Directory {
+        &mut self.directory
+    }
+
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    // Only records the region, mapping its pages up front is up to the caller. Merges it with
+    // its neighbours if they continue each other
+    pub fn add_region(&mut self, region: Region) {
+        let index = self
+            .regions
+            .partition_point(|other| other.range.start < region.range.start);
+
+        let overlaps_previous = index
+            .checked_sub(1)
+            .map_or(false, |i| self.regions[i].range.end > region.range.start);
+        let overlaps_next = self
+            .regions
+            .get(index)
+            .map_or(false, |next| next.range.start < region.range.end);
+        assert!(
+            !overlaps_previous && !overlaps_next,
+            "{:?} overlaps an existing region",
+            region
+        );
+
+        self.regions.insert(index, region);
+
+        if index + 1 < self.regions.len() {
+            self.merge_with_next(index);
+        }
+        if index > 0 {
+            self.merge_with_next(index - 1);
+        }
+    }
+
+    fn merge_with_next(&mut self, index: usize) {
+        if self.regions[index].can_merge(&self.regions[index + 1]) {
+            let next = self.regions.remove(index + 1);
+            self.regions[index].range.end = next.range.end;
+        }
+    }
+
+    pub fn region(&self, address: usize) -> Option<&Region> {
+        let index = self
+            .regions
+            .partition_point(|region| region.range.start <= address);
+
+        index
+            .checked_sub(1)
+            .map(|i| &self.regions[i])
+            .filter(|region| region.contains(address))
+    }
+
+    // Lowest address in `window` aligned to `align` with `size` bytes no region uses
+    pub fn find_free(&self, window: Range<usize>, size: usize, align: usize) -> Option<usize> {
+        assert!(align.is_power_of_two() && align >= PAGE_SIZE);
+        assert!(size > 0 && size % PAGE_SIZE == 0);
+
+        let align_up = |address: usize| address.checked_add(align - 1).map(|a| a & !(align - 1));
+
+        let mut candidate = align_up(window.start)?;
+        for region in self.regions.iter().filter(|r| r.range.end > window.start) {
+            if region.range.start >= candidate.checked_add(size)? {
+                break;
+            }
+            if region.range.end > candidate {
+                candidate = align_up(region.range.end)?;
+            }
+        }
+
+        (candidate.checked_add(size)? <= window.end).then_some(candidate)
+    }
+
+    // Splits the regions crossing the ends of `range`, returns the indices of those inside it
+    fn split(&mut self, range: Range<usize>) -> Range<usize> {
+        assert!(range.start % PAGE_SIZE == 0 && range.end % PAGE_SIZE == 0);
+
+        for at in [range.start, range.end] {
+            let index = self
+                .regions
+                .partition_point(|region| region.range.end <= at);
+            if let Some(region) = self.regions.get(index).filter(|r| r.range.start < at) {
+                let back = region.slice(at..region.range.end);
+                self.regions[index].range.end = at;
+                self.regions.insert(index + 1, back);
+            }
+        }
+
+        let start = self
+            .regions
+            .partition_point(|region| region.range.end <= range.start);
+        let end = self
+            .regions
+            .partition_point(|region| region.range.start < range.end);
+        start..end
+    }
+
+    // Unmaps every page in `range` and forgets the regions there, regions sticking out of it
+    // are split. Frames of anonymous and file backed pages go back to `allocator` once no other
+    // address space shares them
+    pub fn unmap(&mut self, range: Range<usize>, allocator: &mut impl FrameAllocator) {
+        let indices = self.split(range);
+
+        for region in self.regions.drain(indices) {
+            for address in region.range.clone().step_by(PAGE_SIZE) {
+                if let Some(frame) = self.directory.unmap(Page::containing_address(address)) {
+                    if region.owns_frames() && refcount::release(&frame) {
+                        allocator.deallocate(frame);
+                    }
+                }
+            }
+        }
+    }
+
+    // A copy of everything below the kernel that shares its frames. Pages the regions allow
+    // writing to become read-only in both address spaces and get copied by whichever side
+    // writes first. Above that the copy maps the kernel through the same tables
+    pub fn clone_cow(&mut self, allocator: &mut impl FrameAllocator) -> AddressSpace {
+        let mut clone = AddressSpace::new(self.directory.new_sharing_kernel(allocator));
+
+        for region in self.regions.iter().filter(|r| r.range.end <= KERNEL_OFFSET) {
+            clone.regions.push(region.clone());
+
+            for address in region.range.clone().step_by(PAGE_SIZE) {
+                let page = Page::containing_address(address);
+                let Some(physical) = self.directory.translate(address) else {
+                    continue;
+                };
+
+                // Device memory stays shared and writable
+                let mut flags = self.directory.flags(page).unwrap();
+                if region.owns_frames() {
+                    if flags.contains(EntryFlags::WRITABLE) {
+                        flags = (flags - EntryFlags::WRITABLE) | EntryFlags::COW;
+                        self.directory.update_flags(page, flags);
+                    }
+                    refcount::share(&Frame::containing_address(physical));
+                }
+
+                clone
+                    .directory
+                    .map_to(page, Frame::containing_address(physical), flags, allocator);
+            }
+        }
+
+        clone
+    }
+
+    // Unmaps everything below the kernel and frees the directory, which can't be the loaded one
+    pub fn free(mut self, allocator: &mut impl FrameAllocator) {
+        self.unmap(0..KERNEL_OFFSET, allocator);
+        self.directory.free(allocator);
+    }
+
+    // The write fault on a copy-on-write page, the last owner just gets to write again
+    fn copy_on_write(
+        &mut self,
+        page: Page,
+        flags: EntryFlags,
+        allocator: &mut impl FrameAllocator,
+    ) -> Result<(), PageFaultError> {
+        let region = self.region(page.start_address()).unwrap();
+        let name = region.name;
+
+        let frame =
+            Frame::containing_address(self.directory.translate(page.start_address()).unwrap());
+        if !refcount::is_shared(&frame) {
+            self.directory
+                .update_flags(page, (flags - EntryFlags::COW) | EntryFlags::WRITABLE);
+            return Ok(());
+        }
+
+        let copy = allocator
+            .allocate()
+            .ok_or(PageFaultError::OutOfMemory(name))?;
+        refcount::release(&frame);
+
+        // Through a buffer on the stack, the old frame isn't mapped anywhere else to copy from,
+        // and the heap's lock might be held by whoever faulted
+        let target = page.start_address() as *mut u8;
+        let mut buffer = [0u8; PAGE_SIZE];
+        unsafe { target.copy_to_nonoverlapping(buffer.as_mut_ptr(), PAGE_SIZE) };
+
+        self.directory.unmap(page);
+        self.directory.map_to(
+            page,
+            copy,
+            (flags - EntryFlags::COW) | EntryFlags::WRITABLE,
+            allocator,
+        );
+        unsafe { target.copy_from_nonoverlapping(buffer.as_ptr(), PAGE_SIZE) };
+
+        Ok(())
+    }
+
+    // Changes the permissions of the regions and the pages mapped in `range`
+    pub fn protect(&mut self, range: Range<usize>, flags: EntryFlags) {
+        let indices = self.split(range);
+
+        for region in &mut self.regions[indices.clone()] {
+            region.flags = flags;
+
+            for address in region.range.clone().step_by(PAGE_SIZE) {
+                let page = Page::containing_address(address);
+                if self.directory.translate(address).is_some() {
+                    self.directory.update_flags(page, flags);
+                }
+            }
+        }
+
+        // The neighbours on both sides might match again
+        for index in (indices.start.saturating_sub(1)..indices.end).rev() {
+            if index + 1 < self.regions.len() {
+                self.merge_with_next(index);
+            }
+        }
+    }
+
+    pub fn handle_fault(
+        &mut self,
+        address: usize,
+        error: PageFaultErrorCode,
+        allocator: &mut impl FrameAllocator,
+    ) -> Result<(), PageFaultError> {
+        let region = self.region(address).ok_or(PageFaultError::NoRegion)?;
+        let (name, flags) = (region.name, region.flags);
+
+        let backing = match region.kind {
+            RegionKind::Guard => return Err(PageFaultError::Guard(name)),
+            RegionKind::Reserved => return Err(PageFaultError::Protection(name)),
+            RegionKind::Mapped(backing) => backing,
+        };
+
+        if !region.allows(error) {
+            return Err(PageFaultError::Protection(name));
+        }
+
+        let page = Page::containing_address(address);
+        if error.contains(PageFaultErrorCode::PRESENT) {
+            return match self.directory.flags(page) {
+                Some(flags)
+                    if flags.contains(EntryFlags::COW)
+                        && error.contains(PageFaultErrorCode::WRITE) =>
+                {
+                    self.copy_on_write(page, flags, allocator)
+                }
+                _ => Err(PageFaultError::Protection(name)),
+            };
+        }
+
+        let offset = page.start_address() - region.range.start;
+
+        if let Backing::Physical { start } = backing {
+            let frame = Frame::containing_address(start + offset);
+            self.directory.map_to(page, frame, flags, allocator);
+
+            return Ok(());
+        }
+
+        let frame = allocator
+            .allocate()
+            .ok_or(PageFaultError::OutOfMemory(name))?;
+
+        // Fill the frame through a writable mapping before handing out the real permissions
+        self.directory
+            .map_to(page, frame, flags | EntryFlags::WRITABLE, allocator);
+
+        let target = page.start_address() as *mut u8;
+        unsafe { target.write_bytes(0, PAGE_SIZE) };
+
+        if let Backing::File { data, offset: base } = backing {
+            let start = (base + offset).min(data.len());
+            let end = (start + PAGE_SIZE).min(data.len());
+            unsafe { target.copy_from_nonoverlapping(data[start..end].as_ptr(), end - start) };
+        }
+
+        if !flags.contains(EntryFlags::WRITABLE) {
+            self.directory.update_flags(page, flags);
+        }
+
+        Ok(())
+    }
+}
@@ -49,6 +49,8 @@ impl<'a> FrameAllocator for AreaFrameAllocator<'a> {
 }
 
 impl<'a> AreaFrameAllocator<'a> {
+    // `kernel` and `multiboot` are physical ranges, not where the kernel sees them
+    #[allow(dead_code)]
     pub fn new(
         kernel: (usize, usize),
         multiboot: (usize, usize),
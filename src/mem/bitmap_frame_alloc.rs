@@ -0,0 +1,165 @@
+use multiboot2::{MemoryArea, MemoryAreaType};
+
+use super::{Frame, FrameAllocator, PAGE_SIZE};
+
+// Enough bits for every frame of the 32-bit physical address space
+const MAX_FRAMES: usize = 1 << 20;
+const BITS_PER_WORD: usize = u32::BITS as usize;
+const BITMAP_WORDS: usize = MAX_FRAMES / BITS_PER_WORD;
+
+// Low memory holds the real mode IVT, the BIOS data area, the EBDA and the AP trampoline
+const LOW_MEMORY_END: usize = 0x100000;
+
+// A set bit marks a free frame, so the bitmap starts out with everything in use
+static mut BITMAP: [u32; BITMAP_WORDS] = [0; BITMAP_WORDS];
+static mut BITMAP_TAKEN: bool = false;
+
+pub struct BitmapFrameAllocator {
+    bitmap: &'static mut [u32; BITMAP_WORDS],
+    // No frame below this one is free
+    next_free: usize,
+    total_frames: usize,
+    free_frames: usize,
+    kernel_start: Frame,
+    kernel_end: Frame,
+    multiboot_start: Frame,
+    multiboot_end: Frame,
+}
+
+impl FrameAllocator for BitmapFrameAllocator {
+    fn allocate(&mut self) -> Option<Frame> {
+        let start = self.next_free / BITS_PER_WORD;
+
+        let (index, word) = self.bitmap[start..]
+            .iter()
+            .enumerate()
+            .find(|(_, word)| **word != 0)
+            .map(|(index, word)| (start + index, *word))?;
+
+        let number = index * BITS_PER_WORD + word.trailing_zeros() as usize;
+        self.set_free(number, false);
+        self.free_frames -= 1;
+        self.next_free = number + 1;
+
+        Some(Frame { number })
+    }
+
+    fn deallocate(&mut self, frame: Frame) {
+        assert!(
+            frame.number < MAX_FRAMES && !self.is_reserved(frame.number),
+            "Deallocating frame {} which was never allocatable",
+            frame
+        );
+
+        if self.is_free(&frame) {
+            panic!("Double free of frame {}", frame);
+        }
+
+        self.set_free(frame.number, true);
+        self.free_frames += 1;
+        self.next_free = self.next_free.min(frame.number);
+    }
+}
+
+impl BitmapFrameAllocator {
+    // `kernel` and `multiboot` are physical ranges, not where the kernel sees them
+    pub fn new(
+        kernel: (usize, usize),
+        multiboot: (usize, usize),
+        areas: &[MemoryArea],
+    ) -> BitmapFrameAllocator {
+        let bitmap = unsafe {
+            assert!(!BITMAP_TAKEN, "The frame bitmap is already in use");
+            BITMAP_TAKEN = true;
+
+            &mut BITMAP
+        };
+
+        let mut allocator = BitmapFrameAllocator {
+            bitmap,
+            next_free: MAX_FRAMES,
+            total_frames: 0,
+            free_frames: 0,
+            kernel_start: Frame::containing_address(kernel.0),
+            kernel_end: Frame::containing_address(kernel.1),
+            multiboot_start: Frame::containing_address(multiboot.0),
+            multiboot_end: Frame::containing_address(multiboot.1),
+        };
+
+        for area in areas
+            .iter()
+            .filter(|area| area.typ() == MemoryAreaType::Available)
+        {
+            // Only frames that lie completely inside the area are usable
+            let page_size = PAGE_SIZE as u64;
+            let first = ((area.start_address() + page_size - 1) / page_size).min(MAX_FRAMES as u64)
+                as usize;
+            let end =
+                ((area.start_address() + area.size()) / page_size).min(MAX_FRAMES as u64) as usize;
+
+            for number in first..end {
+                if allocator.is_reserved(number) || allocator.bitmap_bit(number) {
+                    continue;
+                }
+
+                allocator.set_free(number, true);
+                allocator.total_frames += 1;
+                allocator.free_frames += 1;
+                allocator.next_free = allocator.next_free.min(number);
+            }
+        }
+
+        allocator
+    }
+
+    fn is_reserved(&self, number: usize) -> bool {
+        number < LOW_MEMORY_END / PAGE_SIZE
+            || (number >= self.kernel_start.number && number <= self.kernel_end.number)
+            || (number >= self.multiboot_start.number && number <= self.multiboot_end.number)
+    }
+
+    fn bitmap_bit(&self, number: usize) -> bool {
+        self.bitmap[number / BITS_PER_WORD] & (1 << (number % BITS_PER_WORD)) != 0
+    }
+
+    fn set_free(&mut self, number: usize, free: bool) {
+        let word = &mut self.bitmap[number / BITS_PER_WORD];
+        let bit = 1 << (number % BITS_PER_WORD);
+
+        if free {
+            *word |= bit;
+        } else {
+            *word &= !bit;
+        }
+    }
+
+    pub fn is_free(&self, frame: &Frame) -> bool {
+        frame.number < MAX_FRAMES && self.bitmap_bit(frame.number)
+    }
+
+    pub fn total_frames(&self) -> usize {
+        self.total_frames
+    }
+
+    pub fn free_frames(&self) -> usize {
+        self.free_frames
+    }
+
+    pub fn used_frames(&self) -> usize {
+        self.total_frames - self.free_frames
+    }
+}
+
+impl core::fmt::Debug for BitmapFrameAllocator {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "BitmapFrameAllocator {{ total: {} frames, free: {} frames ({} KiB), used: {} frames ({} KiB) }}",
+            self.total_frames,
+            self.free_frames,
+            self.free_frames * PAGE_SIZE / 1024,
+            self.used_frames(),
+            self.used_frames() * PAGE_SIZE / 1024
+        )
+    }
+}
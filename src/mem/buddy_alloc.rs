@@ -0,0 +1,285 @@
+use core::ops::Range;
+
+use super::zone::Zone;
+use super::{Frame, FrameAllocator, PAGE_SIZE};
+
+// Blocks of up to 2^10 frames, i.e. 4 MiB
+pub const MAX_ORDER: usize = 10;
+
+const MAX_FRAMES: usize = 1 << 20;
+const BITS_PER_WORD: usize = u32::BITS as usize;
+
+const fn order_words(order: usize) -> usize {
+    (MAX_FRAMES >> order) / BITS_PER_WORD
+}
+
+const fn order_offset(order: usize) -> usize {
+    let mut offset = 0;
+    let mut i = 0;
+    while i < order {
+        offset += order_words(i);
+        i += 1;
+    }
+    offset
+}
+
+const BITMAP_WORDS: usize = order_offset(MAX_ORDER + 1);
+
+// One bitmap per order, a set bit marks a free block of that order. Keeping the free lists
+// out of the frames themselves means they don't need to be mapped to be managed.
+static mut BITMAP: [u32; BITMAP_WORDS] = [0; BITMAP_WORDS];
+static mut BITMAP_TAKEN: bool = false;
+
+pub struct BuddyAllocator {
+    bitmap: &'static mut [u32; BITMAP_WORDS],
+    free_blocks: [usize; MAX_ORDER + 1],
+    // No block of the order below this one is free
+    next_free: [usize; MAX_ORDER + 1],
+    total_frames: usize,
+    // Per zone, blocks never straddle zones since zone boundaries are 4 MiB aligned
+    zone_frames: [usize; 3],
+    zone_free: [usize; 3],
+}
+
+impl FrameAllocator for BuddyAllocator {
+    fn allocate(&mut self) -> Option<Frame> {
+        self.allocate_order(0)
+    }
+
+    fn deallocate(&mut self, frame: Frame) {
+        self.deallocate_order(frame, 0);
+    }
+}
+
+impl BuddyAllocator {
+    // Takes over every frame the bootstrap allocator has left
+    pub fn new(bootstrap: &mut impl FrameAllocator) -> BuddyAllocator {
+        let bitmap = unsafe {
+            assert!(!BITMAP_TAKEN, "The buddy bitmap is already in use");
+            BITMAP_TAKEN = true;
+
+            &mut BITMAP
+        };
+
+        let mut allocator = BuddyAllocator {
+            bitmap,
+            free_blocks: [0; MAX_ORDER + 1],
+            next_free: [usize::MAX; MAX_ORDER + 1],
+            total_frames: 0,
+            zone_frames: [0; 3],
+            zone_free: [0; 3],
+        };
+
+        while let Some(frame) = bootstrap.allocate() {
+            allocator.total_frames += 1;
+            allocator.zone_frames[zone_of(frame.number, 0).index()] += 1;
+            allocator.free(frame.number, 0);
+        }
+
+        allocator
+    }
+
+    fn is_free_block(&self, order: usize, block: usize) -> bool {
+        let index = order_offset(order) + block / BITS_PER_WORD;
+        self.bitmap[index] & (1 << (block % BITS_PER_WORD)) != 0
+    }
+
+    fn insert(&mut self, order: usize, block: usize) {
+        let index = order_offset(order) + block / BITS_PER_WORD;
+        self.bitmap[index] |= 1 << (block % BITS_PER_WORD);
+
+        self.free_blocks[order] += 1;
+        self.next_free[order] = self.next_free[order].min(block);
+        self.zone_free[zone_of(block, order).index()] += 1 << order;
+    }
+
+    fn remove(&mut self, order: usize, block: usize) {
+        let index = order_offset(order) + block / BITS_PER_WORD;
+        self.bitmap[index] &= !(1 << (block % BITS_PER_WORD));
+
+        self.free_blocks[order] -= 1;
+        self.zone_free[zone_of(block, order).index()] -= 1 << order;
+    }
+
+    // First free block of `order` in `blocks`
+    fn find(&mut self, order: usize, blocks: Range<usize>) -> Option<usize> {
+        if self.free_blocks[order] == 0 {
+            return None;
+        }
+
+        let first = blocks.start.max(self.next_free[order]);
+        if first >= blocks.end {
+            return None;
+        }
+
+        let offset = order_offset(order);
+        let start = first / BITS_PER_WORD;
+        let end = (blocks.end + BITS_PER_WORD - 1) / BITS_PER_WORD;
+
+        let found = self.bitmap[offset + start..offset + end]
+            .iter()
+            .enumerate()
+            .find_map(|(index, word)| {
+                let base = (start + index) * BITS_PER_WORD;
+                let mut word = *word;
+
+                // Leave out the blocks on either side of the range
+                if base < first {
+                    word &= !0 << (first - base);
+                }
+                if base + BITS_PER_WORD > blocks.end {
+                    word &= (1 << (blocks.end - base)) - 1;
+                }
+
+                (word != 0).then(|| base + word.trailing_zeros() as usize)
+            });
+
+        // Only a search that started at the hint can move it
+        if blocks.start <= self.next_free[order] {
+            self.next_free[order] = found.unwrap_or(blocks.end);
+        }
+
+        found
+    }
+
+    fn free(&mut self, mut block: usize, mut order: usize) {
+        while order < MAX_ORDER && self.is_free_block(order, block ^ 1) {
+            self.remove(order, block ^ 1);
+
+            block >>= 1;
+            order += 1;
+        }
+
+        self.insert(order, block);
+    }
+
+    // Returns the first frame of 2^order naturally aligned, physically contiguous frames
+    pub fn allocate_order(&mut self, order: usize) -> Option<Frame> {
+        Zone::PREFERENCE
+            .iter()
+            .find_map(|&zone| self.allocate_order_in(order, zone))
+    }
+
+    pub fn allocate_in(&mut self, zone: Zone) -> Option<Frame> {
+        self.allocate_order_in(0, zone)
+    }
+
+    // Like `allocate_order`, but the block comes from `zone` or not at all
+    pub fn allocate_order_in(&mut self, order: usize, zone: Zone) -> Option<Frame> {
+        assert!(order <= MAX_ORDER, "Order {} is above {}", order, MAX_ORDER);
+
+        let frames = zone_frames(zone);
+        let (mut current, mut block) = (order..=MAX_ORDER).find_map(|current| {
+            let blocks = (frames.start >> current)..(frames.end >> current);
+            Some((current, self.find(current, blocks)?))
+        })?;
+        self.remove(current, block);
+
+        // Hand the upper halves of the split block back
+        while current > order {
+            current -= 1;
+            block <<= 1;
+
+            self.insert(current, block | 1);
+        }
+
+        Some(Frame {
+            number: block << order,
+        })
+    }
+
+    pub fn deallocate_order(&mut self, frame: Frame, order: usize) {
+        assert!(order <= MAX_ORDER, "Order {} is above {}", order, MAX_ORDER);
+        assert!(
+            frame.number < MAX_FRAMES && frame.number % (1 << order) == 0,
+            "Frame {} is not the start of an order {} block",
+            frame,
+            order
+        );
+
+        // The block is still free if it or a block containing it is on a free list, and partly
+        // free if any of the smaller blocks it's made of is
+        let block = frame.number >> order;
+        let containing_free = (order..=MAX_ORDER)
+            .any(|current| self.is_free_block(current, block >> (current - order)));
+        let part_free = (0..order).any(|current| {
+            let shift = order - current;
+            ((block << shift)..((block + 1) << shift)).any(|part| self.is_free_block(current, part))
+        });
+        if containing_free || part_free {
+            panic!("Double free of frame {} (order {})", frame, order);
+        }
+
+        self.free(block, order);
+    }
+
+    pub fn free_blocks(&self, order: usize) -> usize {
+        self.free_blocks[order]
+    }
+
+    pub fn total_frames(&self) -> usize {
+        self.total_frames
+    }
+
+    pub fn free_frames(&self) -> usize {
+        self.free_blocks
+            .iter()
+            .enumerate()
+            .map(|(order, count)| count << order)
+            .sum()
+    }
+
+    pub fn used_frames(&self) -> usize {
+        self.total_frames - self.free_frames()
+    }
+
+    pub fn total_frames_in(&self, zone: Zone) -> usize {
+        self.zone_frames[zone.index()]
+    }
+
+    pub fn free_frames_in(&self, zone: Zone) -> usize {
+        self.zone_free[zone.index()]
+    }
+}
+
+fn zone_of(block: usize, order: usize) -> Zone {
+    Zone::containing_address(((block << order) * PAGE_SIZE) as u64)
+}
+
+// Frame numbers of the part of `zone` the bitmap covers
+fn zone_frames(zone: Zone) -> Range<usize> {
+    let range = zone.range();
+    let frame = |address: u64| (address / PAGE_SIZE as u64).min(MAX_FRAMES as u64) as usize;
+
+    frame(range.start)..frame(range.end)
+}
+
+impl core::fmt::Debug for BuddyAllocator {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "BuddyAllocator {{ total: {} frames, free: {} frames ({} KiB), used: {} frames, free blocks: [",
+            self.total_frames,
+            self.free_frames(),
+            self.free_frames() * PAGE_SIZE / 1024,
+            self.used_frames()
+        )?;
+
+        for order in 0..=MAX_ORDER {
+            if order != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}: {}", order, self.free_blocks(order))?;
+        }
+
+        write!(f, "], free per zone: [")?;
+        for zone in Zone::ALL {
+            if zone != Zone::Dma {
+                write!(f, ", ")?;
+            }
+            write!(f, "{:?}: {}", zone, self.free_frames_in(zone))?;
+        }
+
+        write!(f, "] }}")
+    }
+}
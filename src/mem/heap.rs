@@ -0,0 +1,217 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use super::paging::{EntryFlags, Mapper, Page};
+use super::{FrameAllocator, PAGE_SIZE};
+use crate::cpu;
+
+pub const HEAP_START: usize = 0xD000_0000;
+pub const HEAP_SIZE: usize = 4 * 1024 * 1024;
+
+#[global_allocator]
+static ALLOCATOR: Locked<LinkedListAllocator> = Locked::new(LinkedListAllocator::new());
+
+struct FreeRegion {
+    size: usize,
+    next: *mut FreeRegion,
+}
+
+const MIN_REGION_SIZE: usize = core::mem::size_of::<FreeRegion>();
+
+// First fit over an address ordered free list, neighbouring regions are merged on free
+pub struct LinkedListAllocator {
+    head: *mut FreeRegion,
+    size: usize,
+    used: usize,
+}
+
+unsafe impl Send for LinkedListAllocator {}
+
+impl LinkedListAllocator {
+    pub const fn new() -> LinkedListAllocator {
+        LinkedListAllocator {
+            head: core::ptr::null_mut(),
+            size: 0,
+            used: 0,
+        }
+    }
+
+    pub unsafe fn init(&mut self, start: usize, size: usize) {
+        self.size = size;
+        self.add_free_region(start, size);
+    }
+
+    // Every block must be able to hold a `FreeRegion` once it is freed again
+    fn size_align(layout: Layout) -> (usize, usize) {
+        let layout = layout
+            .align_to(core::mem::align_of::<FreeRegion>())
+            .expect("Adjusting the alignment failed")
+            .pad_to_align();
+
+        (layout.size().max(MIN_REGION_SIZE), layout.align())
+    }
+
+    unsafe fn add_free_region(&mut self, address: usize, size: usize) {
+        assert!(address % core::mem::align_of::<FreeRegion>() == 0);
+        assert!(size >= MIN_REGION_SIZE);
+
+        let mut previous: *mut FreeRegion = core::ptr::null_mut();
+        let mut current = self.head;
+        while !current.is_null() && (current as usize) < address {
+            previous = current;
+            current = (*current).next;
+        }
+
+        let region = address as *mut FreeRegion;
+        region.write(FreeRegion {
+            size,
+            next: current,
+        });
+
+        if previous.is_null() {
+            self.head = region;
+        } else {
+            (*previous).next = region;
+        }
+
+        if !current.is_null() && address + size == current as usize {
+            (*region).size += (*current).size;
+            (*region).next = (*current).next;
+        }
+
+        if !previous.is_null() && previous as usize + (*previous).size == address {
+            (*previous).size += (*region).size;
+            (*previous).next = (*region).next;
+        }
+    }
+
+    unsafe fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let (size, align) = Self::size_align(layout);
+
+        let mut previous: *mut FreeRegion = core::ptr::null_mut();
+        let mut current = self.head;
+        while !current.is_null() {
+            let region_start = current as usize;
+            let region_end = region_start + (*current).size;
+
+            let start = (region_start + align - 1) & !(align - 1);
+            let end = start.saturating_add(size);
+
+            // Leftovers have to be big enough to stay on the free list
+            let front = start - region_start;
+            let back = region_end.saturating_sub(end);
+            if end <= region_end
+                && (front == 0 || front >= MIN_REGION_SIZE)
+                && (back == 0 || back >= MIN_REGION_SIZE)
+            {
+                let next = (*current).next;
+                if previous.is_null() {
+                    self.head = next;
+                } else {
+                    (*previous).next = next;
+                }
+
+                if front > 0 {
+                    self.add_free_region(region_start, front);
+                }
+                if back > 0 {
+                    self.add_free_region(end, back);
+                }
+
+                self.used += size;
+                return start as *mut u8;
+            }
+
+            previous = current;
+            current = (*current).next;
+        }
+
+        core::ptr::null_mut()
+    }
+
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = Self::size_align(layout);
+
+        self.add_free_region(ptr as usize, size);
+        self.used -= size;
+    }
+}
+
+pub struct Locked<T> {
+    locked: AtomicBool,
+    inner: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for Locked<T> {}
+
+impl<T> Locked<T> {
+    pub const fn new(inner: T) -> Locked<T> {
+        Locked {
+            locked: AtomicBool::new(false),
+            inner: UnsafeCell::new(inner),
+        }
+    }
+
+    pub fn lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        cpu::without_interrupts(|| {
+            while self
+                .locked
+                .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
+            {
+                core::hint::spin_loop();
+            }
+
+            let result = f(unsafe { &mut *self.inner.get() });
+            self.locked.store(false, Ordering::Release);
+
+            result
+        })
+    }
+}
+
+unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.lock(|allocator| allocator.allocate(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.lock(|allocator| allocator.deallocate(ptr, layout));
+    }
+}
+
+#[alloc_error_handler]
+fn alloc_error(layout: Layout) -> ! {
+    panic!(
+        "Kernel heap exhausted allocating {} bytes (align {}), {} of {} bytes in use",
+        layout.size(),
+        layout.align(),
+        used(),
+        HEAP_SIZE
+    );
+}
+
+pub fn used() -> usize {
+    ALLOCATOR.lock(|allocator| allocator.used)
+}
+
+pub fn free() -> usize {
+    ALLOCATOR.lock(|allocator| allocator.size - allocator.used)
+}
+
+pub fn init(mapper: &mut impl Mapper, allocator: &mut impl FrameAllocator) {
+    for address in (HEAP_START..HEAP_START + HEAP_SIZE).step_by(PAGE_SIZE) {
+        let frame = allocator
+            .allocate()
+            .expect("Out of memory for the kernel heap");
+        mapper.map_to(
+            Page::containing_address(address),
+            frame,
+            EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE,
+            allocator,
+        );
+    }
+
+    ALLOCATOR.lock(|heap| unsafe { heap.init(HEAP_START, HEAP_SIZE) });
+}
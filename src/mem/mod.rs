@@ -1,8 +1,28 @@
+pub mod address_space;
 mod area_frame_alloc;
+mod bitmap_frame_alloc;
+mod buddy_alloc;
+pub mod heap;
+pub mod paging;
+mod refcount;
+mod remap;
+pub mod slab;
+pub mod vmm;
+pub mod zone;
 
 use core::fmt::Display;
 
+use crate::cpu::{self, gdt, idt::PageFaultErrorCode, smp};
+use address_space::{AddressSpace, Backing, PageFaultError, Region, RegionKind};
+use multiboot2::MemoryAreaType;
+use paging::EntryFlags;
+use paging::{Directory, Mapper, Page};
+use zone::{Stats, Zone};
+
 pub use area_frame_alloc::AreaFrameAllocator;
+pub use bitmap_frame_alloc::BitmapFrameAllocator;
+pub use buddy_alloc::{BuddyAllocator, MAX_ORDER};
+pub use remap::remap_kernel;
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Frame {
@@ -11,6 +31,26 @@ pub struct Frame {
 
 pub const PAGE_SIZE: usize = 4096;
 
+// Everything but the boot section is linked this far above where it is loaded, see linker.ld.
+// Above it live the kernel image, the heap, the slab window, the vmm window and the identity
+// mapped chipset devices and page table recursion at the very top
+pub const KERNEL_OFFSET: usize = 0xC000_0000;
+
+// Physical memory the boot directory maps at the offset, `BOOT_WINDOW_ENTRIES` 4 MiB pages in
+// bootloader.S
+pub const BOOT_WINDOW_SIZE: usize = 16 * 4 * 1024 * 1024;
+
+// Physical address of something in the kernel image, addresses below the offset belong to the
+// boot section, which is linked at its physical address
+pub fn kernel_to_physical(address: usize) -> usize {
+    address.checked_sub(KERNEL_OFFSET).unwrap_or(address)
+}
+
+// Where low physical memory, like the VGA buffer or the multiboot information, is reached
+pub const fn physical_to_kernel(address: usize) -> usize {
+    address + KERNEL_OFFSET
+}
+
 impl Display for Frame {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(
@@ -24,7 +64,7 @@ impl Display for Frame {
 }
 
 impl Frame {
-    fn containing_address(address: usize) -> Frame {
+    pub fn containing_address(address: usize) -> Frame {
         Frame {
             number: address / PAGE_SIZE,
         }
@@ -43,3 +83,148 @@ pub trait FrameAllocator {
     fn allocate(&mut self) -> Option<Frame>;
     fn deallocate(&mut self, frame: Frame);
 }
+
+static mut FRAME_ALLOCATOR: Option<BuddyAllocator> = None;
+static mut KERNEL_ADDRESS_SPACE: Option<AddressSpace> = None;
+// The loaded address space, unless it's the kernel's
+static mut CURRENT_ADDRESS_SPACE: Option<*mut AddressSpace> = None;
+
+extern "C" {
+    // Page right below the boot stack, see bootloader.S
+    static stack_guard: u8;
+}
+
+pub fn with_frame_allocator<R>(f: impl FnOnce(&mut BuddyAllocator) -> R) -> R {
+    cpu::without_interrupts(|| {
+        let allocator = unsafe { FRAME_ALLOCATOR.as_mut() };
+        f(allocator.expect("Frame allocator is not initialized"))
+    })
+}
+
+pub fn with_kernel_address_space<R>(
+    f: impl FnOnce(&mut AddressSpace, &mut BuddyAllocator) -> R,
+) -> R {
+    cpu::without_interrupts(|| {
+        let address_space = unsafe { KERNEL_ADDRESS_SPACE.as_mut() };
+        let allocator = unsafe { FRAME_ALLOCATOR.as_mut() };
+
+        f(
+            address_space.expect("Kernel address space is not initialized"),
+            allocator.expect("Frame allocator is not initialized"),
+        )
+    })
+}
+
+pub fn with_kernel_directory<R>(f: impl FnOnce(&mut Directory, &mut BuddyAllocator) -> R) -> R {
+    with_kernel_address_space(|address_space, allocator| {
+        f(address_space.directory_mut(), allocator)
+    })
+}
+
+// Memory map totals filled in with what the frame allocator manages and has free right now
+pub fn stats() -> Stats {
+    let mut stats = zone::memory_map().expect("Memory map is not recorded");
+
+    with_frame_allocator(|allocator| {
+        for zone in Zone::ALL {
+            let frames = &mut stats.zones[zone.index()];
+            frames.managed = allocator.total_frames_in(zone);
+            frames.free = allocator.free_frames_in(zone);
+        }
+
+        // Only available memory is ever given to the allocator
+        for area in stats.areas.iter_mut().flatten() {
+            if area.typ == MemoryAreaType::Available {
+                area.frames.managed = allocator.total_frames();
+                area.frames.free = allocator.free_frames();
+            }
+        }
+    });
+
+    stats
+}
+
+// The double fault task switch loads CR3 as well, so it has to follow the directory this CPU
+// runs on
+unsafe fn load(directory: &Directory) {
+    directory.load();
+    gdt::set_double_fault_cr3(
+        smp::current_index(),
+        directory.root_frame().start_address() as u32,
+    );
+}
+
+// Loads `address_space`, which must neither move nor be dropped until another one is loaded
+pub unsafe fn switch_to(address_space: &mut AddressSpace) {
+    cpu::without_interrupts(|| {
+        load(address_space.directory());
+        CURRENT_ADDRESS_SPACE = Some(address_space);
+    });
+}
+
+pub fn switch_to_kernel() {
+    with_kernel_address_space(|address_space, _| unsafe {
+        load(address_space.directory());
+        CURRENT_ADDRESS_SPACE = None;
+    });
+}
+
+// Called by the #PF handler, before `init` every page fault is fatal. Faults go to the loaded
+// address space, except in the kernel's part, which every address space maps through the
+// kernel's tables
+pub fn handle_page_fault(address: usize, error: PageFaultErrorCode) -> Result<(), PageFaultError> {
+    let initialized = unsafe { KERNEL_ADDRESS_SPACE.is_some() && FRAME_ALLOCATOR.is_some() };
+    if !initialized {
+        return Err(PageFaultError::NoRegion);
+    }
+
+    with_kernel_address_space(|kernel, allocator| {
+        let address_space = if address >= KERNEL_OFFSET || kernel.directory().is_active() {
+            kernel
+        } else {
+            match unsafe { CURRENT_ADDRESS_SPACE } {
+                Some(current) if unsafe { (*current).directory().is_active() } => unsafe {
+                    &mut *current
+                },
+                _ => return Err(PageFaultError::NoRegion),
+            }
+        };
+
+        address_space.handle_fault(address, error, allocator)
+    })
+}
+
+// Name of the guard region `address` lies in, for telling stack overflows apart from other
+// faults. Doesn't lock anything, so it can be used from the double fault task
+pub fn guard_region(address: usize) -> Option<&'static str> {
+    let address_space = unsafe { KERNEL_ADDRESS_SPACE.as_ref() }?;
+
+    address_space
+        .region(address)
+        .filter(|region| region.kind() == RegionKind::Guard)
+        .map(|region| region.name())
+}
+
+pub fn init(kernel_directory: Directory, frame_allocator: BuddyAllocator) {
+    let mut address_space = AddressSpace::new(kernel_directory);
+
+    // The guard page is part of the kernel image, its frame stays reserved
+    let guard = unsafe { &stack_guard as *const u8 as usize };
+    address_space
+        .directory_mut()
+        .unmap(Page::containing_address(guard));
+    address_space.add_region(Region::guard("boot stack", guard..guard + PAGE_SIZE));
+
+    address_space.add_region(Region::new(
+        "heap",
+        heap::HEAP_START..heap::HEAP_START + heap::HEAP_SIZE,
+        RegionKind::Mapped(Backing::Anonymous),
+        EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE,
+    ));
+    address_space.add_region(Region::reserved("slabs", slab::SLAB_START..slab::SLAB_END));
+
+    unsafe {
+        KERNEL_ADDRESS_SPACE = Some(address_space);
+        FRAME_ALLOCATOR = Some(frame_allocator);
+    }
+}
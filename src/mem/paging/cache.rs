@@ -0,0 +1,139 @@
+use core::arch::asm;
+
+use super::{flush_tlb_all, EntryFlags};
+use crate::cpu::{
+    self,
+    msr::{rdmsr, wrmsr, IA32_PAT},
+};
+
+const CR0_NOT_WRITE_THROUGH: u32 = 1 << 29;
+const CR0_CACHE_DISABLE: u32 = 1 << 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheType {
+    WriteBack,
+    WriteThrough,
+    // Like Uncacheable, but an MTRR saying write-combining wins
+    UncacheableMinus,
+    Uncacheable,
+    WriteCombining,
+}
+
+impl CacheType {
+    // Memory type encoding used by the PAT
+    fn encoding(self) -> u64 {
+        match self {
+            CacheType::Uncacheable => 0x00,
+            CacheType::WriteCombining => 0x01,
+            CacheType::WriteThrough => 0x04,
+            CacheType::WriteBack => 0x06,
+            CacheType::UncacheableMinus => 0x07,
+        }
+    }
+}
+
+// The first half matches the power-on default, so entries without the PAT bit mean the same
+// whether the PAT was programmed or not
+const PAT_LAYOUT: [CacheType; 8] = [
+    CacheType::WriteBack,
+    CacheType::WriteThrough,
+    CacheType::UncacheableMinus,
+    CacheType::Uncacheable,
+    CacheType::WriteCombining,
+    CacheType::WriteThrough,
+    CacheType::UncacheableMinus,
+    CacheType::Uncacheable,
+];
+
+static mut PAT_ENABLED: bool = false;
+
+fn pat_index(flags: EntryFlags) -> usize {
+    (flags.contains(EntryFlags::WRITE_THROUGH) as usize)
+        | ((flags.contains(EntryFlags::CACHE_DISABLE) as usize) << 1)
+        | ((flags.contains(EntryFlags::PAT) as usize) << 2)
+}
+
+impl EntryFlags {
+    pub const CACHE_TYPE: EntryFlags = EntryFlags::WRITE_THROUGH
+        .union(EntryFlags::CACHE_DISABLE)
+        .union(EntryFlags::PAT);
+
+    // Without a PAT, write-combining falls back to UC-, which still lets an MTRR make it WC
+    pub fn with_cache(self, cache: CacheType) -> EntryFlags {
+        let cache = if cache == CacheType::WriteCombining && !pat_enabled() {
+            CacheType::UncacheableMinus
+        } else {
+            cache
+        };
+
+        let index = PAT_LAYOUT.iter().position(|&c| c == cache).unwrap();
+
+        let mut flags = self - EntryFlags::CACHE_TYPE;
+        flags.set(EntryFlags::WRITE_THROUGH, index & 0b001 != 0);
+        flags.set(EntryFlags::CACHE_DISABLE, index & 0b010 != 0);
+        flags.set(EntryFlags::PAT, index & 0b100 != 0);
+        flags
+    }
+
+    pub fn cache_type(self) -> CacheType {
+        let index = pat_index(self);
+        if pat_enabled() {
+            PAT_LAYOUT[index]
+        } else {
+            PAT_LAYOUT[index & 0b011]
+        }
+    }
+}
+
+pub fn pat_enabled() -> bool {
+    unsafe { PAT_ENABLED }
+}
+
+// Every CPU has to be programmed with the same layout. The caches and TLBs can't hold anything
+// typed by the old layout while it changes, so this goes through the sequence the SDM gives for
+// changing memory types
+pub fn load_pat() {
+    let value = PAT_LAYOUT
+        .iter()
+        .enumerate()
+        .fold(0, |value, (i, cache)| value | (cache.encoding() << (i * 8)));
+
+    cpu::without_interrupts(|| unsafe {
+        let cr0: u32;
+        asm!("mov {}, cr0", out(reg) cr0, options(nomem, nostack, preserves_flags));
+
+        // No-fill cache mode, then flush what's cached already
+        let no_fill = (cr0 | CR0_CACHE_DISABLE) & !CR0_NOT_WRITE_THROUGH;
+        asm!("mov cr0, {}", in(reg) no_fill, options(nostack, preserves_flags));
+        asm!("wbinvd", options(nostack, preserves_flags));
+        flush_tlb_all();
+
+        wrmsr(IA32_PAT, value);
+
+        asm!("wbinvd", options(nostack, preserves_flags));
+        flush_tlb_all();
+        asm!(
+            "mov cr0, {}",
+            in(reg) cr0 & !CR0_CACHE_DISABLE,
+            options(nostack, preserves_flags)
+        );
+    });
+}
+
+pub fn init_pat() {
+    load_pat();
+    unsafe { PAT_ENABLED = true };
+}
+
+// What the PAT currently holds, None for types CacheType doesn't cover
+pub fn read_pat() -> [Option<CacheType>; 8] {
+    let value = unsafe { rdmsr(IA32_PAT) };
+
+    core::array::from_fn(|i| {
+        let encoding = (value >> (i * 8)) & 0x7;
+        PAT_LAYOUT
+            .iter()
+            .copied()
+            .find(|cache| cache.encoding() == encoding)
+    })
+}
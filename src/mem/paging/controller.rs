@@ -1,30 +1,213 @@
 use super::*;
+use crate::mem::{Frame, FrameAllocator, KERNEL_OFFSET};
 use core::arch::asm;
 
+// The last directory entry points back at the directory, which makes every page table of the
+// active directory visible in the top 4 MiB and the directory itself in the top page
+pub const RECURSIVE_INDEX: usize = ENTRY_COUNT - 1;
+const RECURSIVE_TABLES: usize = RECURSIVE_INDEX * ENTRY_COUNT * PAGE_SIZE;
+const RECURSIVE_DIRECTORY: usize = RECURSIVE_TABLES + RECURSIVE_INDEX * PAGE_SIZE;
+
+// The entry below it does the same for a directory that isn't loaded, it is pointed at whichever
+// one was used last. That directory shows up in the window's slot of the recursive tables
+pub const WINDOW_INDEX: usize = RECURSIVE_INDEX - 1;
+const WINDOW_TABLES: usize = WINDOW_INDEX * ENTRY_COUNT * PAGE_SIZE;
+const WINDOW_DIRECTORY: usize = RECURSIVE_TABLES + WINDOW_INDEX * PAGE_SIZE;
+
+// First entry of the kernel's part of the address space
+const KERNEL_INDEX: usize = KERNEL_OFFSET / PSE_LARGE_PAGE_SIZE;
+
+pub const PSE_LARGE_PAGE_SIZE: usize = ENTRY_COUNT * PAGE_SIZE;
+
 pub struct PagingController {
-    directory: &'static [PageDirectoryEntry; 1024],
+    directory: usize,
+    pse: bool,
+    pse36: bool,
+}
+
+pub fn paging_enabled() -> bool {
+    let cr0: u32;
+    unsafe { asm!("mov {}, cr0", out(reg) cr0, options(nomem, nostack, preserves_flags)) };
+
+    cr0 & (1 << 31) != 0
+}
+
+pub fn active_directory() -> usize {
+    let cr3: u32;
+    unsafe { asm!("mov {}, cr3", out(reg) cr3, options(nomem, nostack, preserves_flags)) };
+
+    (cr3 & 0xFFFFF000) as usize
 }
 
 impl PagingController {
-    pub unsafe fn initialize_at(address: usize) -> Self {
+    // `pse36` only matters with `pse`, it lets 4 MiB pages reach physical memory above 4 GiB
+    pub unsafe fn initialize_at(address: usize, pse: bool, pse36: bool) -> Self {
         assert!(address % 4096 == 0);
 
-        let directory = &mut *(address as *mut _);
-        core::ptr::write_bytes(
-            directory,
-            0,
-            core::mem::size_of::<[PageDirectoryEntry; 1024]>(),
+        let mut controller = Self {
+            directory: address,
+            pse,
+            pse36: pse && pse36,
+        };
+
+        let directory = controller.directory_mut();
+        directory.fill(PageDirectoryEntry::new_raw(0));
+        directory[RECURSIVE_INDEX] =
+            PageDirectoryEntry::new(address as *mut PageTableEntry, false, true, true);
+
+        controller
+    }
+
+    pub fn new(pse: bool, pse36: bool, allocator: &mut impl FrameAllocator) -> Self {
+        let frame = allocator
+            .allocate()
+            .expect("Out of memory for the page directory");
+
+        unsafe { Self::initialize_at(frame.start_address(), pse, pse36) }
+    }
+
+    pub fn directory_frame(&self) -> Frame {
+        Frame::containing_address(self.directory)
+    }
+
+    pub fn pse_enabled(&self) -> bool {
+        self.pse
+    }
+
+    pub fn pse36_enabled(&self) -> bool {
+        self.pse36
+    }
+
+    pub fn is_active(&self) -> bool {
+        active_directory() == self.directory
+    }
+
+    fn window_entry() -> &'static mut PageDirectoryEntry {
+        unsafe {
+            &mut (*(RECURSIVE_DIRECTORY as *mut [PageDirectoryEntry; ENTRY_COUNT]))[WINDOW_INDEX]
+        }
+    }
+
+    fn is_attached(&self) -> bool {
+        let entry = Self::window_entry();
+        entry.is_present() && entry.address() as usize == self.directory
+    }
+
+    // Points the window at this directory, references into the one it showed before are stale
+    fn attach(&self) {
+        if !self.is_attached() {
+            *Self::window_entry() =
+                PageDirectoryEntry::new(self.directory as *mut PageTableEntry, false, true, true);
+            flush_tlb_all();
+        }
+    }
+
+    fn directory_address(&self) -> usize {
+        match access(self.directory) {
+            Access::Recursive => RECURSIVE_DIRECTORY,
+            Access::Window => {
+                self.attach();
+                WINDOW_DIRECTORY
+            }
+            Access::Physical => self.directory,
+        }
+    }
+
+    pub fn directory(&self) -> &[PageDirectoryEntry; ENTRY_COUNT] {
+        unsafe { &*(self.directory_address() as *const _) }
+    }
+
+    pub fn directory_mut(&mut self) -> &mut [PageDirectoryEntry; ENTRY_COUNT] {
+        unsafe { &mut *(self.directory_address() as *mut _) }
+    }
+
+    pub fn table_address(&self, index: usize) -> usize {
+        assert!(
+            index < WINDOW_INDEX,
+            "Directory entry {} has no table",
+            index
         );
 
-        Self { directory }
+        match access(self.directory) {
+            Access::Recursive => RECURSIVE_TABLES + index * PAGE_SIZE,
+            Access::Window => {
+                self.attach();
+                WINDOW_TABLES + index * PAGE_SIZE
+            }
+            Access::Physical => self.directory()[index].address() as usize,
+        }
     }
 
+    // The window and recursive entries point at directories, not tables
+    pub fn table(&self, index: usize) -> Option<&[PageTableEntry; ENTRY_COUNT]> {
+        let entry = self.directory()[index];
+        if index >= WINDOW_INDEX || !entry.is_present() || entry.is_4m() {
+            return None;
+        }
+
+        Some(unsafe { &*(self.table_address(index) as *const _) })
+    }
+
+    pub fn table_mut(&mut self, index: usize) -> Option<&mut [PageTableEntry; ENTRY_COUNT]> {
+        let entry = self.directory()[index];
+        if index >= WINDOW_INDEX || !entry.is_present() || entry.is_4m() {
+            return None;
+        }
+
+        Some(unsafe { &mut *(self.table_address(index) as *mut _) })
+    }
+
+    // Gives every kernel directory entry a table, so the kernel's entries never change once
+    // `new_sharing_kernel` copied them and everything mapped there later shows up in the copies
+    pub fn allocate_kernel_tables(&mut self, allocator: &mut impl FrameAllocator) {
+        for index in KERNEL_INDEX..WINDOW_INDEX {
+            if !self.directory()[index].is_present() {
+                self.create_table(index, false, allocator);
+            }
+        }
+    }
+
+    // A new directory that maps the kernel's part of the address space through the same tables
+    // as this one, which needs `allocate_kernel_tables` to have been called on it
+    pub fn new_sharing_kernel(&self, allocator: &mut impl FrameAllocator) -> Self {
+        let mut controller = Self::new(self.pse, self.pse36, allocator);
+
+        for index in KERNEL_INDEX..WINDOW_INDEX {
+            controller.directory_mut()[index] = self.directory()[index];
+        }
+
+        controller
+    }
+
+    // Frees the directory and its tables below the kernel, the kernel's tables are shared
+    pub fn free(self, allocator: &mut impl FrameAllocator) {
+        assert!(!self.is_active(), "Freeing the loaded page directory");
+
+        for index in 0..KERNEL_INDEX {
+            let entry = self.directory()[index];
+            if entry.is_present() && !entry.is_4m() {
+                allocator.deallocate(Frame::containing_address(entry.address() as usize));
+            }
+        }
+
+        if matches!(access(self.directory), Access::Window) && self.is_attached() {
+            *Self::window_entry() = unsafe { PageDirectoryEntry::new_raw(0) };
+            flush_tlb_all();
+        }
+
+        allocator.deallocate(self.directory_frame());
+    }
+
+    // Also sets CR0.WP, so read-only pages are read-only for the kernel too
     pub unsafe fn enable_paging(&self) {
-        asm!(
-            "mov cr3, {tbl}",
-            "mov cr0, cr0 | 0x80000000",
+        let mut cr4 = read_cr4() & !CR4_PAE;
+        if self.pse {
+            cr4 |= CR4_PSE;
+        } else {
+            cr4 &= !CR4_PSE;
+        }
 
-            tbl = in(reg) self.directory.as_ptr()
-        );
+        load_paging(self.directory as u32, cr4);
+        RECURSIVE_LOADED = true;
     }
 }
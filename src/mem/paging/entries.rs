@@ -1,3 +1,5 @@
+use super::PSE_LARGE_PAGE_SIZE;
+
 #[derive(Clone, Copy)]
 #[repr(transparent)]
 pub struct PageDirectoryEntry {
@@ -27,14 +29,44 @@ impl PageDirectoryEntry {
         unsafe { Self::new_raw(inner) }
     }
 
+    // Maps a 4 MiB page, physical address bits 32-39 need PSE-36 and end up in bits 13-20
+    pub fn new_4m(address: u64, user: bool, rw: bool, present: bool) -> Self {
+        assert!(address % PSE_LARGE_PAGE_SIZE as u64 == 0);
+        assert!(address < (1 << 40));
+
+        let ps = 1;
+        let accessed = false; // Initially not accessed
+        let cache_disable = false;
+        let write_through = false;
+
+        let inner = ((address as u32) & 0xFFC00000)
+            | ((((address >> 32) as u32) & 0xFF) << 13)
+            | ((ps as u32) << 7)
+            | ((accessed as u32) << 5)
+            | ((cache_disable as u32) << 4)
+            | ((write_through as u32) << 3)
+            | ((user as u32) << 2)
+            | ((rw as u32) << 1)
+            | (present as u32);
+
+        unsafe { Self::new_raw(inner) }
+    }
+
     pub unsafe fn new_raw(inner: u32) -> Self {
         Self { inner }
     }
 
+    pub fn large_address(&self) -> u64 {
+        assert!(self.is_4m());
+
+        ((self.inner & 0xFFC00000) as u64) | ((((self.inner >> 13) & 0xFF) as u64) << 32)
+    }
+
     pub fn address(&self) -> *mut PageTableEntry {
         (self.inner & 0xFFFFF000) as *mut PageTableEntry
     }
 
+    #[allow(dead_code)]
     pub fn set_address(&mut self, address: *mut PageTableEntry) -> &mut Self {
         self.inner = (self.inner & !0xFFFFF000) | ((address as u32) & 0xFFFFF000);
         self
@@ -44,15 +76,29 @@ impl PageDirectoryEntry {
         (self.inner & (1 << 7)) != 0
     }
 
+    #[allow(dead_code)]
     pub fn set_4m(&mut self, is_4m: bool) -> &mut Self {
         self.inner = (self.inner & !(1 << 7)) | ((is_4m as u32) << 7);
         self
     }
 
+    // Only for 4 MiB entries, bit 12 holds the table address otherwise
+    pub fn is_pat(&self) -> bool {
+        assert!(self.is_4m());
+        (self.inner & (1 << 12)) != 0
+    }
+
+    pub fn set_pat(&mut self, is_pat: bool) -> &mut Self {
+        assert!(self.is_4m());
+        self.inner = (self.inner & !(1 << 12)) | ((is_pat as u32) << 12);
+        self
+    }
+
     pub fn was_accessed(&self) -> bool {
         (self.inner & (1 << 5)) != 0
     }
 
+    #[allow(dead_code)]
     pub fn set_was_accessed(&mut self, was_accessed: bool) -> &mut Self {
         self.inner = (self.inner & !(1 << 5)) | ((was_accessed as u32) << 5);
         self
@@ -98,14 +144,29 @@ impl PageDirectoryEntry {
         (self.inner & (1 << 0)) != 0
     }
 
+    #[allow(dead_code)]
     pub fn set_present(&mut self, is_present: bool) -> &mut Self {
-        self.inner = (self.inner & !(1 << 0)) | ((is_present as u32) << 0);
+        self.inner = (self.inner & !(1 << 0)) | (is_present as u32);
         self
     }
 }
 
 impl core::fmt::Debug for PageDirectoryEntry {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if self.is_4m() {
+            return f
+                .debug_struct("PageDirectoryEntry4m")
+                .field("address", &format_args!("0x{:010X}", self.large_address()))
+                .field("was_accessed", &self.was_accessed())
+                .field("is_pat", &self.is_pat())
+                .field("cache_disabled", &self.cache_disabled())
+                .field("is_write_through", &self.is_write_through())
+                .field("is_user", &self.is_user())
+                .field("is_read_write", &self.is_read_write())
+                .field("is_present", &self.is_present())
+                .finish();
+        }
+
         f.debug_struct("PageDirectoryEntry4k")
             .field("address", &self.address())
             .field("is_4m", &self.is_4m())
@@ -122,15 +183,13 @@ impl core::fmt::Debug for PageDirectoryEntry {
 #[derive(Clone, Copy)]
 #[repr(transparent)]
 pub struct PageTableEntry {
-    inner: u32
+    inner: u32,
 }
 
 impl PageTableEntry {
     pub fn new(address: usize, user: bool, rw: bool, present: bool) -> Self {
-        let inner = 0;
-
         let global = false; // TODO: Do we need global
-        let pat = 0; // Assume not supported
+        let pat = false; // Write back, see CacheType for the others
         let dirty = false; // Initially not dirty
         let accessed = false; // Initially not accessed
         let cache_disable = false;
@@ -159,11 +218,22 @@ impl PageTableEntry {
         (self.inner & 0xFFFFF000) as usize
     }
 
+    #[allow(dead_code)]
     pub fn set_address(&mut self, address: usize) -> &mut Self {
         self.inner = (self.inner & !0xFFFFF000) | ((address as u32) & 0xFFFFF000);
         self
     }
 
+    // First of the available bits, marks pages that are copied on the next write
+    pub fn is_cow(&self) -> bool {
+        (self.inner & (1 << 9)) != 0
+    }
+
+    pub fn set_cow(&mut self, is_cow: bool) -> &mut Self {
+        self.inner = (self.inner & !(1 << 9)) | ((is_cow as u32) << 9);
+        self
+    }
+
     pub fn is_global(&self) -> bool {
         (self.inner & (1 << 8)) != 0
     }
@@ -173,28 +243,32 @@ impl PageTableEntry {
         self
     }
 
-    pub fn is_4m(&self) -> bool {
+    pub fn is_pat(&self) -> bool {
         (self.inner & (1 << 7)) != 0
     }
 
-    pub fn set_4m(&mut self, is_4m: bool) -> &mut Self {
-        self.inner = (self.inner & !(1 << 7)) | ((is_4m as u32) << 7);
+    pub fn set_pat(&mut self, is_pat: bool) -> &mut Self {
+        self.inner = (self.inner & !(1 << 7)) | ((is_pat as u32) << 7);
         self
     }
 
+    #[allow(dead_code)]
     pub fn is_dirty(&self) -> bool {
         (self.inner & (1 << 6)) != 0
     }
 
+    #[allow(dead_code)]
     pub fn set_dirty(&mut self, is_dirty: bool) -> &mut Self {
         self.inner = (self.inner & !(1 << 6)) | ((is_dirty as u32) << 6);
         self
     }
 
+    #[allow(dead_code)]
     pub fn was_accessed(&self) -> bool {
         (self.inner & (1 << 5)) != 0
     }
 
+    #[allow(dead_code)]
     pub fn set_was_accessed(&mut self, was_accessed: bool) -> &mut Self {
         self.inner = (self.inner & !(1 << 5)) | ((was_accessed as u32) << 5);
         self
@@ -240,8 +314,9 @@ impl PageTableEntry {
         (self.inner & (1 << 0)) != 0
     }
 
+    #[allow(dead_code)]
     pub fn set_present(&mut self, is_present: bool) -> &mut Self {
-        self.inner = (self.inner & !(1 << 0)) | ((is_present as u32) << 0);
+        self.inner = (self.inner & !(1 << 0)) | (is_present as u32);
         self
     }
 }
@@ -0,0 +1,375 @@
+use core::ops::Range;
+
+use bitflags::bitflags;
+
+use super::*;
+use crate::mem::{Frame, FrameAllocator};
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct EntryFlags: u32 {
+        const WRITABLE = 1 << 1;
+        const USER = 1 << 2;
+        const WRITE_THROUGH = 1 << 3;
+        const CACHE_DISABLE = 1 << 4;
+        const GLOBAL = 1 << 8;
+        // Not a hardware bit, the page is shared read-only and gets copied on the next write
+        const COW = 1 << 9;
+        // Selects the upper half of the PAT together with the two bits above, see CacheType
+        const PAT = 1 << 7;
+        // Only honoured by PAE paging with NX enabled
+        const NO_EXECUTE = 1 << 31;
+    }
+}
+
+pub trait Mapper {
+    fn map_to(
+        &mut self,
+        page: Page,
+        frame: Frame,
+        flags: EntryFlags,
+        allocator: &mut impl FrameAllocator,
+    );
+
+    // Size of the pages a directory entry can map directly, if the mode supports them
+    fn large_page_size(&self) -> Option<usize> {
+        None
+    }
+
+    // Maps a whole large page, returns false if that part of the directory is already in use
+    fn map_large_to(&mut self, _address: usize, _physical: u64, _flags: EntryFlags) -> bool {
+        false
+    }
+
+    // Returns the frame that was mapped, freeing it is up to the caller
+    fn unmap(&mut self, page: Page) -> Option<Frame>;
+
+    fn translate(&self, address: usize) -> Option<usize>;
+
+    fn flags(&self, page: Page) -> Option<EntryFlags>;
+
+    fn update_flags(&mut self, page: Page, flags: EntryFlags);
+
+    // Pages that are already identity mapped keep their mapping and gain the permissions of `flags`.
+    // Aligned stretches of untouched address space are mapped with large pages when possible
+    fn identity_map(
+        &mut self,
+        range: Range<usize>,
+        flags: EntryFlags,
+        allocator: &mut impl FrameAllocator,
+    ) {
+        let physical = range.start;
+        self.map_range(range, physical, flags, allocator);
+    }
+
+    // Maps `range` to the physical memory starting at `physical`, which has the same offset into
+    // its page as the range. Pages that already map the right frame are handled as for
+    // `identity_map`
+    fn map_range(
+        &mut self,
+        range: Range<usize>,
+        physical: usize,
+        flags: EntryFlags,
+        allocator: &mut impl FrameAllocator,
+    ) {
+        if range.is_empty() {
+            return;
+        }
+
+        assert!(
+            range.start % PAGE_SIZE == physical % PAGE_SIZE,
+            "0x{:08X} and 0x{:08X} have different page offsets",
+            range.start,
+            physical
+        );
+
+        let end = range.end - 1;
+        let mut address = Page::containing_address(range.start).start_address();
+        let mut frame_address = Frame::containing_address(physical).start_address();
+
+        loop {
+            let size = match self.large_page_size() {
+                Some(size)
+                    if address % size == 0
+                        && frame_address % size == 0
+                        && end - address >= size - 1
+                        && self.map_large_to(address, frame_address as u64, flags) =>
+                {
+                    size
+                }
+                _ => {
+                    self.map_page_merging(
+                        Page::containing_address(address),
+                        Frame::containing_address(frame_address),
+                        flags,
+                        allocator,
+                    );
+                    PAGE_SIZE
+                }
+            };
+
+            match address.checked_add(size) {
+                Some(next) if next <= end => {
+                    address = next;
+                    frame_address += size;
+                }
+                _ => break,
+            }
+        }
+    }
+
+    fn map_page_merging(
+        &mut self,
+        page: Page,
+        frame: Frame,
+        flags: EntryFlags,
+        allocator: &mut impl FrameAllocator,
+    ) {
+        match self.translate(page.start_address()) {
+            None => self.map_to(page, frame, flags, allocator),
+            Some(address) if address == frame.start_address() => {
+                // Shared pages stay executable if either mapping needs that, and keep the cache
+                // type they were mapped with first
+                let existing = self.flags(page).unwrap();
+                let no_execute = existing & flags & EntryFlags::NO_EXECUTE;
+                let cache = existing & EntryFlags::CACHE_TYPE;
+                let merged = ((existing | flags) - EntryFlags::NO_EXECUTE - EntryFlags::CACHE_TYPE)
+                    | no_execute
+                    | cache;
+
+                if merged != existing {
+                    self.update_flags(page, merged);
+                }
+            }
+            Some(address) => panic!(
+                "Page {} is already mapped to 0x{:08X}, can't map it to {}",
+                page, address, frame
+            ),
+        }
+    }
+}
+
+fn directory_entry_flags(entry: &PageDirectoryEntry) -> EntryFlags {
+    let mut flags = EntryFlags::empty();
+    flags.set(EntryFlags::PAT, entry.is_pat());
+    flags.set(EntryFlags::WRITABLE, entry.is_read_write());
+    flags.set(EntryFlags::USER, entry.is_user());
+    flags.set(EntryFlags::WRITE_THROUGH, entry.is_write_through());
+    flags.set(EntryFlags::CACHE_DISABLE, entry.cache_disabled());
+    flags
+}
+
+fn entry_flags(entry: &PageTableEntry) -> EntryFlags {
+    let mut flags = EntryFlags::empty();
+    flags.set(EntryFlags::PAT, entry.is_pat());
+    flags.set(EntryFlags::WRITABLE, entry.is_read_write());
+    flags.set(EntryFlags::USER, entry.is_user());
+    flags.set(EntryFlags::WRITE_THROUGH, entry.is_write_through());
+    flags.set(EntryFlags::CACHE_DISABLE, entry.cache_disabled());
+    flags.set(EntryFlags::GLOBAL, entry.is_global());
+    flags.set(EntryFlags::COW, entry.is_cow());
+    flags
+}
+
+fn set_entry_flags(entry: &mut PageTableEntry, flags: EntryFlags) {
+    entry
+        .set_read_write(flags.contains(EntryFlags::WRITABLE))
+        .set_user(flags.contains(EntryFlags::USER))
+        .set_write_through(flags.contains(EntryFlags::WRITE_THROUGH))
+        .set_cache_disabled(flags.contains(EntryFlags::CACHE_DISABLE))
+        .set_pat(flags.contains(EntryFlags::PAT))
+        .set_global(flags.contains(EntryFlags::GLOBAL))
+        .set_cow(flags.contains(EntryFlags::COW));
+}
+
+impl PagingController {
+    pub(super) fn create_table(
+        &mut self,
+        index: usize,
+        user: bool,
+        allocator: &mut impl FrameAllocator,
+    ) -> &mut [PageTableEntry; ENTRY_COUNT] {
+        let entry = &mut self.directory_mut()[index];
+        assert!(
+            !entry.is_4m(),
+            "Directory entry {} maps a 4 MiB page",
+            index
+        );
+
+        if entry.is_present() {
+            // Access is restricted per page, the directory entry has to allow everything
+            if user {
+                entry.set_user(true);
+            }
+        } else {
+            let frame = allocator.allocate().expect("Out of memory for page tables");
+            *entry = PageDirectoryEntry::new(
+                frame.start_address() as *mut PageTableEntry,
+                user,
+                true,
+                true,
+            );
+
+            // A stale translation for the table's recursive address might still be cached
+            flush_tlb(self.table_address(index));
+            self.table_mut(index)
+                .unwrap()
+                .fill(unsafe { PageTableEntry::new_raw(0) });
+        }
+
+        self.table_mut(index).unwrap()
+    }
+}
+
+impl Mapper for PagingController {
+    fn large_page_size(&self) -> Option<usize> {
+        self.pse_enabled().then_some(PSE_LARGE_PAGE_SIZE)
+    }
+
+    fn map_large_to(&mut self, address: usize, physical: u64, flags: EntryFlags) -> bool {
+        assert!(self.pse_enabled(), "4 MiB pages need PSE");
+        assert!(address % PSE_LARGE_PAGE_SIZE == 0);
+
+        let index = Page::containing_address(address).directory_index();
+        assert!(
+            index < WINDOW_INDEX,
+            "0x{:08X} lies in the recursive mappings",
+            address
+        );
+
+        if physical > u32::MAX as u64 && !self.pse36_enabled() {
+            return false;
+        }
+
+        if self.directory()[index].is_present() {
+            return false;
+        }
+
+        let mut entry = PageDirectoryEntry::new_4m(
+            physical,
+            flags.contains(EntryFlags::USER),
+            flags.contains(EntryFlags::WRITABLE),
+            true,
+        );
+        entry
+            .set_write_through(flags.contains(EntryFlags::WRITE_THROUGH))
+            .set_cache_disabled(flags.contains(EntryFlags::CACHE_DISABLE))
+            .set_pat(flags.contains(EntryFlags::PAT));
+
+        self.directory_mut()[index] = entry;
+        flush_tlb(address);
+
+        true
+    }
+
+    fn map_to(
+        &mut self,
+        page: Page,
+        frame: Frame,
+        flags: EntryFlags,
+        allocator: &mut impl FrameAllocator,
+    ) {
+        assert!(
+            page.directory_index() < WINDOW_INDEX,
+            "Page {} lies in the recursive mappings",
+            page
+        );
+
+        let table = self.create_table(
+            page.directory_index(),
+            flags.contains(EntryFlags::USER),
+            allocator,
+        );
+
+        let entry = &mut table[page.table_index()];
+        assert!(!entry.is_present(), "Page {} is already mapped", page);
+
+        *entry = PageTableEntry::new(
+            frame.start_address(),
+            flags.contains(EntryFlags::USER),
+            flags.contains(EntryFlags::WRITABLE),
+            true,
+        );
+        set_entry_flags(entry, flags);
+
+        flush_tlb(page.start_address());
+    }
+
+    fn unmap(&mut self, page: Page) -> Option<Frame> {
+        let entry = &mut self.table_mut(page.directory_index())?[page.table_index()];
+        if !entry.is_present() {
+            return None;
+        }
+
+        let frame = Frame::containing_address(entry.address());
+        *entry = unsafe { PageTableEntry::new_raw(0) };
+
+        flush_tlb(page.start_address());
+
+        Some(frame)
+    }
+
+    fn translate(&self, address: usize) -> Option<usize> {
+        let page = Page::containing_address(address);
+        let directory_entry = self.directory()[page.directory_index()];
+        if !directory_entry.is_present() {
+            return None;
+        }
+
+        if directory_entry.is_4m() {
+            // Physical addresses above 4 GiB don't fit the return type
+            let base = usize::try_from(directory_entry.large_address()).ok()?;
+            return Some(base + address % PSE_LARGE_PAGE_SIZE);
+        }
+
+        let entry = self.table(page.directory_index())?[page.table_index()];
+        if !entry.is_present() {
+            return None;
+        }
+
+        Some(entry.address() + address % PAGE_SIZE)
+    }
+
+    fn flags(&self, page: Page) -> Option<EntryFlags> {
+        let directory_entry = self.directory()[page.directory_index()];
+        if directory_entry.is_present() && directory_entry.is_4m() {
+            return Some(directory_entry_flags(&directory_entry));
+        }
+
+        let entry = self.table(page.directory_index())?[page.table_index()];
+        if !entry.is_present() {
+            return None;
+        }
+
+        Some(entry_flags(&entry))
+    }
+
+    fn update_flags(&mut self, page: Page, flags: EntryFlags) {
+        if flags.contains(EntryFlags::USER) {
+            self.directory_mut()[page.directory_index()].set_user(true);
+        }
+
+        let directory_entry = &mut self.directory_mut()[page.directory_index()];
+        if directory_entry.is_present() && directory_entry.is_4m() {
+            directory_entry
+                .set_read_write(flags.contains(EntryFlags::WRITABLE))
+                .set_user(flags.contains(EntryFlags::USER))
+                .set_write_through(flags.contains(EntryFlags::WRITE_THROUGH))
+                .set_cache_disabled(flags.contains(EntryFlags::CACHE_DISABLE))
+                .set_pat(flags.contains(EntryFlags::PAT));
+
+            flush_tlb(page.start_address());
+            return;
+        }
+
+        let entry = self
+            .table_mut(page.directory_index())
+            .map(|table| &mut table[page.table_index()])
+            .filter(|entry| entry.is_present())
+            .unwrap_or_else(|| panic!("Page {} is not mapped", page));
+
+        set_entry_flags(entry, flags);
+
+        flush_tlb(page.start_address());
+    }
+}
@@ -3,3 +3,252 @@ pub use entries::*;
 
 mod controller;
 pub use controller::*;
+
+mod mapper;
+pub use mapper::*;
+
+mod pae;
+pub use pae::*;
+
+mod cache;
+pub use cache::*;
+
+use core::arch::asm;
+
+use super::{Frame, FrameAllocator, PAGE_SIZE};
+
+pub const ENTRY_COUNT: usize = 1024;
+
+const CR4_PSE: u32 = 1 << 4;
+const CR4_PAE: u32 = 1 << 5;
+
+extern "C" {
+    // Defined in bootloader.S, switches CR3 and CR4 with paging briefly disabled
+    fn load_paging(cr3: u32, cr4: u32);
+}
+
+// Set once one of our directories is loaded, before that the boot directory identity maps
+// everything and tables are reached by their physical address
+static mut RECURSIVE_LOADED: bool = false;
+
+// How the tables of a directory are reached
+enum Access {
+    // It is loaded, through its own recursive entry
+    Recursive,
+    // Through the window entry of the loaded directory, which gets pointed at it
+    Window,
+    Physical,
+}
+
+fn access(root: usize) -> Access {
+    if !paging_enabled() || !unsafe { RECURSIVE_LOADED } {
+        Access::Physical
+    } else if active_directory() == root {
+        Access::Recursive
+    } else {
+        Access::Window
+    }
+}
+
+fn read_cr4() -> u32 {
+    let cr4: u32;
+    unsafe { asm!("mov {}, cr4", out(reg) cr4, options(nomem, nostack, preserves_flags)) };
+
+    cr4
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Page {
+    number: usize,
+}
+
+impl Page {
+    pub fn containing_address(address: usize) -> Page {
+        Page {
+            number: address / PAGE_SIZE,
+        }
+    }
+
+    pub fn start_address(&self) -> usize {
+        self.number * PAGE_SIZE
+    }
+
+    pub fn directory_index(&self) -> usize {
+        self.number / ENTRY_COUNT
+    }
+
+    pub fn table_index(&self) -> usize {
+        self.number % ENTRY_COUNT
+    }
+}
+
+impl core::fmt::Display for Page {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{{idx={},start=0x{:08X}}}",
+            self.number,
+            self.start_address()
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Legacy { pse: bool, pse36: bool },
+    Pae { nx: bool },
+}
+
+pub enum Directory {
+    Legacy(PagingController),
+    Pae(PaePagingController),
+}
+
+impl Directory {
+    pub fn new(mode: Mode, allocator: &mut impl FrameAllocator) -> Directory {
+        match mode {
+            Mode::Legacy { pse, pse36 } => {
+                Directory::Legacy(PagingController::new(pse, pse36, allocator))
+            }
+            Mode::Pae { nx } => Directory::Pae(PaePagingController::new(nx, allocator)),
+        }
+    }
+
+    // Done once for the kernel's directory, before any directory shares its tables
+    pub fn allocate_kernel_tables(&mut self, allocator: &mut impl FrameAllocator) {
+        match self {
+            Directory::Legacy(controller) => controller.allocate_kernel_tables(allocator),
+            Directory::Pae(controller) => controller.allocate_kernel_tables(allocator),
+        }
+    }
+
+    // Same mode, with the kernel's part of the address space mapped through this directory's
+    // tables. Works for any directory once paging is on, the ones that aren't loaded are reached
+    // through the window next to the recursive mapping
+    pub fn new_sharing_kernel(&self, allocator: &mut impl FrameAllocator) -> Directory {
+        match self {
+            Directory::Legacy(controller) => {
+                Directory::Legacy(controller.new_sharing_kernel(allocator))
+            }
+            Directory::Pae(controller) => Directory::Pae(controller.new_sharing_kernel(allocator)),
+        }
+    }
+
+    // Frees the tables below the kernel and the directory itself, what they map stays allocated
+    pub fn free(self, allocator: &mut impl FrameAllocator) {
+        match self {
+            Directory::Legacy(controller) => controller.free(allocator),
+            Directory::Pae(controller) => controller.free(allocator),
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        match self {
+            Directory::Legacy(controller) => Mode::Legacy {
+                pse: controller.pse_enabled(),
+                pse36: controller.pse36_enabled(),
+            },
+            Directory::Pae(controller) => Mode::Pae {
+                nx: controller.nx_enabled(),
+            },
+        }
+    }
+
+    // The frame CR3 points at
+    pub fn root_frame(&self) -> Frame {
+        match self {
+            Directory::Legacy(controller) => controller.directory_frame(),
+            Directory::Pae(controller) => controller.pdpt_frame(),
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.root_frame().start_address() == active_directory()
+    }
+
+    // Switches to this directory, which has to use the paging mode that is enabled
+    pub unsafe fn load(&self) {
+        let cr3 = self.root_frame().start_address() as u32;
+        asm!("mov cr3, {}", in(reg) cr3, options(nostack, preserves_flags));
+    }
+
+    pub unsafe fn enable_paging(&self) {
+        match self {
+            Directory::Legacy(controller) => controller.enable_paging(),
+            Directory::Pae(controller) => controller.enable_paging(),
+        }
+    }
+}
+
+impl Mapper for Directory {
+    fn map_to(
+        &mut self,
+        page: Page,
+        frame: Frame,
+        flags: EntryFlags,
+        allocator: &mut impl FrameAllocator,
+    ) {
+        match self {
+            Directory::Legacy(controller) => controller.map_to(page, frame, flags, allocator),
+            Directory::Pae(controller) => controller.map_to(page, frame, flags, allocator),
+        }
+    }
+
+    fn large_page_size(&self) -> Option<usize> {
+        match self {
+            Directory::Legacy(controller) => controller.large_page_size(),
+            Directory::Pae(controller) => controller.large_page_size(),
+        }
+    }
+
+    fn map_large_to(&mut self, address: usize, physical: u64, flags: EntryFlags) -> bool {
+        match self {
+            Directory::Legacy(controller) => controller.map_large_to(address, physical, flags),
+            Directory::Pae(controller) => controller.map_large_to(address, physical, flags),
+        }
+    }
+
+    fn unmap(&mut self, page: Page) -> Option<Frame> {
+        match self {
+            Directory::Legacy(controller) => controller.unmap(page),
+            Directory::Pae(controller) => controller.unmap(page),
+        }
+    }
+
+    fn translate(&self, address: usize) -> Option<usize> {
+        match self {
+            Directory::Legacy(controller) => controller.translate(address),
+            Directory::Pae(controller) => controller.translate(address),
+        }
+    }
+
+    fn flags(&self, page: Page) -> Option<EntryFlags> {
+        match self {
+            Directory::Legacy(controller) => controller.flags(page),
+            Directory::Pae(controller) => controller.flags(page),
+        }
+    }
+
+    fn update_flags(&mut self, page: Page, flags: EntryFlags) {
+        match self {
+            Directory::Legacy(controller) => controller.update_flags(page, flags),
+            Directory::Pae(controller) => controller.update_flags(page, flags),
+        }
+    }
+}
+
+pub fn flush_tlb(address: usize) {
+    unsafe { asm!("invlpg [{}]", in(reg) address, options(nostack, preserves_flags)) };
+}
+
+// Everything but global pages
+pub fn flush_tlb_all() {
+    unsafe {
+        asm!(
+            "mov {0}, cr3",
+            "mov cr3, {0}",
+            out(reg) _,
+            options(nostack, preserves_flags)
+        )
+    };
+}
@@ -0,0 +1,635 @@
+use super::*;
+use crate::cpu::msr::{rdmsr, wrmsr, IA32_EFER};
+use crate::mem::{Frame, FrameAllocator, KERNEL_OFFSET};
+
+pub const PAE_ENTRY_COUNT: usize = 512;
+pub const PAE_LARGE_PAGE_SIZE: usize = PAE_ENTRY_COUNT * PAGE_SIZE;
+const PDPT_ENTRY_COUNT: usize = 4;
+
+// The last four entries of the last directory point at the four directories, which makes
+// every page table visible in the top 8 MiB and the directories in the top four pages
+const RECURSIVE_DIRECTORY: usize = PDPT_ENTRY_COUNT - 1;
+const RECURSIVE_INDEX: usize = PAE_ENTRY_COUNT - PDPT_ENTRY_COUNT;
+const RECURSIVE_TABLES: usize = 0xFF80_0000;
+const RECURSIVE_DIRECTORIES: usize = 0xFFFF_C000;
+
+// The four entries below them show the directories of one that isn't loaded the same way, see
+// the legacy WINDOW_INDEX
+const WINDOW_INDEX: usize = RECURSIVE_INDEX - PDPT_ENTRY_COUNT;
+const WINDOW_TABLES: usize = 0xFF00_0000;
+const WINDOW_DIRECTORIES: usize = 0xFFFF_8000;
+
+// Directory that covers the kernel's part of the address space
+const KERNEL_DIRECTORY: usize = KERNEL_OFFSET >> 30;
+
+const ADDRESS_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+const LARGE_ADDRESS_MASK: u64 = 0x000F_FFFF_FFE0_0000;
+
+const EFER_NXE: u64 = 1 << 11;
+
+#[derive(Clone, Copy)]
+#[repr(transparent)]
+pub struct PaeEntry {
+    inner: u64,
+}
+
+impl PaeEntry {
+    pub fn new(address: u64, user: bool, rw: bool, present: bool) -> Self {
+        assert!(address % PAGE_SIZE as u64 == 0);
+
+        let inner =
+            (address & ADDRESS_MASK) | ((user as u64) << 2) | ((rw as u64) << 1) | (present as u64);
+
+        unsafe { Self::new_raw(inner) }
+    }
+
+    // Directory entry mapping a 2 MiB page
+    pub fn new_2m(address: u64, user: bool, rw: bool, present: bool) -> Self {
+        assert!(address % PAE_LARGE_PAGE_SIZE as u64 == 0);
+
+        let inner = (address & LARGE_ADDRESS_MASK)
+            | (1 << 7)
+            | ((user as u64) << 2)
+            | ((rw as u64) << 1)
+            | (present as u64);
+
+        unsafe { Self::new_raw(inner) }
+    }
+
+    // PDPT entries only have the present and caching bits, everything else is reserved
+    pub fn new_pdpt(address: u64) -> Self {
+        assert!(address % PAGE_SIZE as u64 == 0);
+
+        unsafe { Self::new_raw((address & ADDRESS_MASK) | 1) }
+    }
+
+    pub unsafe fn new_raw(inner: u64) -> Self {
+        Self { inner }
+    }
+
+    fn bit(&self, bit: u32) -> bool {
+        self.inner & (1 << bit) != 0
+    }
+
+    fn set_bit(&mut self, bit: u32, value: bool) -> &mut Self {
+        self.inner = (self.inner & !(1 << bit)) | ((value as u64) << bit);
+        self
+    }
+
+    pub fn address(&self) -> u64 {
+        if self.is_2m() {
+            self.inner & LARGE_ADDRESS_MASK
+        } else {
+            self.inner & ADDRESS_MASK
+        }
+    }
+
+    pub fn is_no_execute(&self) -> bool {
+        self.bit(63)
+    }
+
+    pub fn set_no_execute(&mut self, no_execute: bool) -> &mut Self {
+        self.set_bit(63, no_execute)
+    }
+
+    // Available to software, see PageTableEntry::is_cow
+    pub fn is_cow(&self) -> bool {
+        self.bit(9)
+    }
+
+    pub fn set_cow(&mut self, is_cow: bool) -> &mut Self {
+        self.set_bit(9, is_cow)
+    }
+
+    pub fn is_global(&self) -> bool {
+        self.bit(8)
+    }
+
+    pub fn set_global(&mut self, is_global: bool) -> &mut Self {
+        self.set_bit(8, is_global)
+    }
+
+    // Only meaningful in directory entries, the bit is PAT in page table entries
+    pub fn is_2m(&self) -> bool {
+        self.bit(7)
+    }
+
+    // Page table entries only
+    pub fn is_pat(&self) -> bool {
+        self.bit(7)
+    }
+
+    pub fn set_pat(&mut self, is_pat: bool) -> &mut Self {
+        self.set_bit(7, is_pat)
+    }
+
+    // 2 MiB entries only
+    pub fn is_large_pat(&self) -> bool {
+        self.bit(12)
+    }
+
+    pub fn set_large_pat(&mut self, is_pat: bool) -> &mut Self {
+        self.set_bit(12, is_pat)
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.bit(6)
+    }
+
+    pub fn was_accessed(&self) -> bool {
+        self.bit(5)
+    }
+
+    pub fn cache_disabled(&self) -> bool {
+        self.bit(4)
+    }
+
+    pub fn set_cache_disabled(&mut self, cache_disabled: bool) -> &mut Self {
+        self.set_bit(4, cache_disabled)
+    }
+
+    pub fn is_write_through(&self) -> bool {
+        self.bit(3)
+    }
+
+    pub fn set_write_through(&mut self, is_write_through: bool) -> &mut Self {
+        self.set_bit(3, is_write_through)
+    }
+
+    pub fn is_user(&self) -> bool {
+        self.bit(2)
+    }
+
+    pub fn set_user(&mut self, is_user: bool) -> &mut Self {
+        self.set_bit(2, is_user)
+    }
+
+    pub fn is_read_write(&self) -> bool {
+        self.bit(1)
+    }
+
+    pub fn set_read_write(&mut self, is_read_write: bool) -> &mut Self {
+        self.set_bit(1, is_read_write)
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.bit(0)
+    }
+
+    #[allow(dead_code)]
+    pub fn set_present(&mut self, is_present: bool) -> &mut Self {
+        self.set_bit(0, is_present)
+    }
+}
+
+impl core::fmt::Debug for PaeEntry {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("PaeEntry")
+            .field("address", &format_args!("0x{:09X}", self.address()))
+            .field("is_no_execute", &self.is_no_execute())
+            .field("is_global", &self.is_global())
+            .field("is_2m", &self.is_2m())
+            .field("is_dirty", &self.is_dirty())
+            .field("was_accessed", &self.was_accessed())
+            .field("cache_disabled", &self.cache_disabled())
+            .field("is_write_through", &self.is_write_through())
+            .field("is_user", &self.is_user())
+            .field("is_read_write", &self.is_read_write())
+            .field("is_present", &self.is_present())
+            .finish()
+    }
+}
+
+fn indices(address: usize) -> (usize, usize, usize) {
+    (
+        address >> 30,
+        (address >> 21) % PAE_ENTRY_COUNT,
+        (address >> 12) % PAE_ENTRY_COUNT,
+    )
+}
+
+pub struct PaePagingController {
+    pdpt: usize,
+    directories: [usize; PDPT_ENTRY_COUNT],
+    nx: bool,
+}
+
+impl PaePagingController {
+    // All four directories are allocated up front, the CPU caches the PDPT on CR3 loads anyway
+    pub fn new(nx: bool, allocator: &mut impl FrameAllocator) -> Self {
+        let mut allocate = || {
+            allocator
+                .allocate()
+                .expect("Out of memory for the page directory")
+                .start_address()
+        };
+
+        let pdpt = allocate();
+        let directories = [(); PDPT_ENTRY_COUNT].map(|_| allocate());
+        let mut controller = Self {
+            pdpt,
+            directories,
+            nx,
+        };
+
+        controller.with_pdpt(|pdpt| {
+            for (entry, directory) in pdpt.iter_mut().zip(directories) {
+                *entry = PaeEntry::new_pdpt(directory as u64);
+            }
+        });
+
+        for index in 0..PDPT_ENTRY_COUNT {
+            controller
+                .directory_mut(index)
+                .fill(unsafe { PaeEntry::new_raw(0) });
+        }
+
+        let recursive = &mut controller.directory_mut(RECURSIVE_DIRECTORY)[RECURSIVE_INDEX..];
+        for (entry, directory) in recursive.iter_mut().zip(directories) {
+            *entry = PaeEntry::new(directory as u64, false, true, true);
+        }
+
+        controller
+    }
+
+    pub fn pdpt_frame(&self) -> Frame {
+        Frame::containing_address(self.pdpt)
+    }
+
+    pub fn nx_enabled(&self) -> bool {
+        self.nx
+    }
+
+    pub fn is_active(&self) -> bool {
+        active_directory() == self.pdpt
+    }
+
+    fn window_entries() -> &'static mut [PaeEntry] {
+        let directory = RECURSIVE_DIRECTORIES + RECURSIVE_DIRECTORY * PAGE_SIZE;
+        let directory = unsafe { &mut *(directory as *mut [PaeEntry; PAE_ENTRY_COUNT]) };
+
+        &mut directory[WINDOW_INDEX..RECURSIVE_INDEX]
+    }
+
+    fn is_attached(&self) -> bool {
+        let entry = Self::window_entries()[0];
+        entry.is_present() && entry.address() == self.directories[0] as u64
+    }
+
+    // Points the window at these directories, references into the ones it showed before are
+    // stale
+    fn attach(&self) {
+        if !self.is_attached() {
+            for (entry, directory) in Self::window_entries().iter_mut().zip(self.directories) {
+                *entry = PaeEntry::new(directory as u64, false, true, true);
+            }
+            flush_tlb_all();
+        }
+    }
+
+    // The PDPT isn't part of any directory, with paging on it is shown in place of the first
+    // window directory while `f` runs
+    fn with_pdpt<R>(&mut self, f: impl FnOnce(&mut [PaeEntry; PDPT_ENTRY_COUNT]) -> R) -> R {
+        match access(self.pdpt) {
+            Access::Physical => f(unsafe { &mut *(self.pdpt as *mut _) }),
+            Access::Window => {
+                let window = Self::window_entries();
+                window[0] = PaeEntry::new(self.pdpt as u64, false, true, true);
+                flush_tlb_all();
+
+                let result = f(unsafe { &mut *(WINDOW_DIRECTORIES as *mut _) });
+
+                window[0] = unsafe { PaeEntry::new_raw(0) };
+                flush_tlb_all();
+                result
+            }
+            Access::Recursive => panic!("The PDPT of the loaded directory is not mapped"),
+        }
+    }
+
+    fn directory_address(&self, index: usize) -> usize {
+        match access(self.pdpt) {
+            Access::Recursive => RECURSIVE_DIRECTORIES + index * PAGE_SIZE,
+            Access::Window => {
+                self.attach();
+                WINDOW_DIRECTORIES + index * PAGE_SIZE
+            }
+            Access::Physical => self.directories[index],
+        }
+    }
+
+    pub fn directory(&self, index: usize) -> &[PaeEntry; PAE_ENTRY_COUNT] {
+        unsafe { &*(self.directory_address(index) as *const _) }
+    }
+
+    pub fn directory_mut(&mut self, index: usize) -> &mut [PaeEntry; PAE_ENTRY_COUNT] {
+        unsafe { &mut *(self.directory_address(index) as *mut _) }
+    }
+
+    pub fn table_address(&self, directory: usize, index: usize) -> usize {
+        match access(self.pdpt) {
+            Access::Recursive => {
+                RECURSIVE_TABLES + (directory * PAE_ENTRY_COUNT + index) * PAGE_SIZE
+            }
+            Access::Window => {
+                self.attach();
+                WINDOW_TABLES + (directory * PAE_ENTRY_COUNT + index) * PAGE_SIZE
+            }
+            Access::Physical => self.directory(directory)[index].address() as usize,
+        }
+    }
+
+    pub fn table(&self, directory: usize, index: usize) -> Option<&[PaeEntry; PAE_ENTRY_COUNT]> {
+        let entry = self.directory(directory)[index];
+        if Self::is_recursive(directory, index) || !entry.is_present() || entry.is_2m() {
+            return None;
+        }
+
+        Some(unsafe { &*(self.table_address(directory, index) as *const _) })
+    }
+
+    pub fn table_mut(
+        &mut self,
+        directory: usize,
+        index: usize,
+    ) -> Option<&mut [PaeEntry; PAE_ENTRY_COUNT]> {
+        let entry = self.directory(directory)[index];
+        if Self::is_recursive(directory, index) || !entry.is_present() || entry.is_2m() {
+            return None;
+        }
+
+        Some(unsafe { &mut *(self.table_address(directory, index) as *mut _) })
+    }
+
+    // The window and recursive entries point at directories, not tables
+    fn is_recursive(directory: usize, index: usize) -> bool {
+        directory == RECURSIVE_DIRECTORY && index >= WINDOW_INDEX
+    }
+
+    fn assert_mappable(address: usize) {
+        let (directory, index, _) = indices(address);
+        assert!(
+            !Self::is_recursive(directory, index),
+            "Address 0x{:08X} lies in the recursive mappings",
+            address
+        );
+    }
+
+    fn create_table(
+        &mut self,
+        directory: usize,
+        index: usize,
+        user: bool,
+        allocator: &mut impl FrameAllocator,
+    ) -> &mut [PaeEntry; PAE_ENTRY_COUNT] {
+        let entry = &mut self.directory_mut(directory)[index];
+        assert!(
+            !entry.is_2m(),
+            "Directory entry {}:{} maps a 2 MiB page",
+            directory,
+            index
+        );
+
+        if entry.is_present() {
+            if user {
+                entry.set_user(true);
+            }
+        } else {
+            let frame = allocator.allocate().expect("Out of memory for page tables");
+            *entry = PaeEntry::new(frame.start_address() as u64, user, true, true);
+
+            flush_tlb(self.table_address(directory, index));
+            self.table_mut(directory, index)
+                .unwrap()
+                .fill(unsafe { PaeEntry::new_raw(0) });
+        }
+
+        self.table_mut(directory, index).unwrap()
+    }
+
+    // `large` tells 2 MiB entries apart from page table entries, they keep PAT in another bit
+    fn set_entry_flags(&self, entry: &mut PaeEntry, flags: EntryFlags, large: bool) {
+        if large {
+            entry.set_large_pat(flags.contains(EntryFlags::PAT));
+        } else {
+            entry.set_pat(flags.contains(EntryFlags::PAT));
+        }
+
+        entry
+            .set_read_write(flags.contains(EntryFlags::WRITABLE))
+            .set_user(flags.contains(EntryFlags::USER))
+            .set_write_through(flags.contains(EntryFlags::WRITE_THROUGH))
+            .set_cache_disabled(flags.contains(EntryFlags::CACHE_DISABLE))
+            .set_global(flags.contains(EntryFlags::GLOBAL))
+            .set_cow(flags.contains(EntryFlags::COW))
+            // The bit is reserved unless EFER.NXE is set
+            .set_no_execute(self.nx && flags.contains(EntryFlags::NO_EXECUTE));
+    }
+
+    // Like the legacy one, fills the kernel's directory up to the window with tables
+    pub fn allocate_kernel_tables(&mut self, allocator: &mut impl FrameAllocator) {
+        for index in 0..WINDOW_INDEX {
+            if !self.directory(KERNEL_DIRECTORY)[index].is_present() {
+                self.create_table(KERNEL_DIRECTORY, index, false, allocator);
+            }
+        }
+    }
+
+    // Like the legacy `new_sharing_kernel`, the kernel has the last directory to itself
+    pub fn new_sharing_kernel(&self, allocator: &mut impl FrameAllocator) -> Self {
+        let mut controller = Self::new(self.nx, allocator);
+
+        for index in 0..WINDOW_INDEX {
+            controller.directory_mut(KERNEL_DIRECTORY)[index] =
+                self.directory(KERNEL_DIRECTORY)[index];
+        }
+
+        controller
+    }
+
+    // Frees the PDPT, the directories and the tables below the kernel
+    pub fn free(self, allocator: &mut impl FrameAllocator) {
+        assert!(!self.is_active(), "Freeing the loaded page directory");
+
+        for directory in 0..KERNEL_DIRECTORY {
+            for entry in self.directory(directory) {
+                if entry.is_present() && !entry.is_2m() {
+                    allocator.deallocate(Frame::containing_address(entry.address() as usize));
+                }
+            }
+        }
+
+        if matches!(access(self.pdpt), Access::Window) && self.is_attached() {
+            Self::window_entries().fill(unsafe { PaeEntry::new_raw(0) });
+            flush_tlb_all();
+        }
+
+        for directory in self.directories {
+            allocator.deallocate(Frame::containing_address(directory));
+        }
+        allocator.deallocate(self.pdpt_frame());
+    }
+
+    pub unsafe fn enable_paging(&self) {
+        if self.nx {
+            wrmsr(IA32_EFER, rdmsr(IA32_EFER) | EFER_NXE);
+        }
+
+        load_paging(self.pdpt as u32, read_cr4() | CR4_PAE);
+        RECURSIVE_LOADED = true;
+    }
+}
+
+impl Mapper for PaePagingController {
+    fn map_to(
+        &mut self,
+        page: Page,
+        frame: Frame,
+        flags: EntryFlags,
+        allocator: &mut impl FrameAllocator,
+    ) {
+        Self::assert_mappable(page.start_address());
+
+        let (directory, index, table_index) = indices(page.start_address());
+        let mut entry = PaeEntry::new(
+            frame.start_address() as u64,
+            flags.contains(EntryFlags::USER),
+            flags.contains(EntryFlags::WRITABLE),
+            true,
+        );
+        self.set_entry_flags(&mut entry, flags, false);
+
+        let table = self.create_table(
+            directory,
+            index,
+            flags.contains(EntryFlags::USER),
+            allocator,
+        );
+
+        let slot = &mut table[table_index];
+        assert!(!slot.is_present(), "Page {} is already mapped", page);
+        *slot = entry;
+
+        flush_tlb(page.start_address());
+    }
+
+    fn large_page_size(&self) -> Option<usize> {
+        Some(PAE_LARGE_PAGE_SIZE)
+    }
+
+    fn map_large_to(&mut self, address: usize, physical: u64, flags: EntryFlags) -> bool {
+        assert!(address % PAE_LARGE_PAGE_SIZE == 0);
+        Self::assert_mappable(address);
+
+        let (directory, index, _) = indices(address);
+        if self.directory(directory)[index].is_present() {
+            return false;
+        }
+
+        let mut entry = PaeEntry::new_2m(
+            physical,
+            flags.contains(EntryFlags::USER),
+            flags.contains(EntryFlags::WRITABLE),
+            true,
+        );
+        self.set_entry_flags(&mut entry, flags, true);
+
+        self.directory_mut(directory)[index] = entry;
+        flush_tlb(address);
+
+        true
+    }
+
+    fn unmap(&mut self, page: Page) -> Option<Frame> {
+        let (directory, index, table_index) = indices(page.start_address());
+        let entry = &mut self.table_mut(directory, index)?[table_index];
+        if !entry.is_present() {
+            return None;
+        }
+
+        let frame = Frame::containing_address(entry.address() as usize);
+        *entry = unsafe { PaeEntry::new_raw(0) };
+
+        flush_tlb(page.start_address());
+
+        Some(frame)
+    }
+
+    fn translate(&self, address: usize) -> Option<usize> {
+        let (directory, index, table_index) = indices(address);
+        let directory_entry = self.directory(directory)[index];
+        if !directory_entry.is_present() {
+            return None;
+        }
+
+        if directory_entry.is_2m() {
+            return Some(directory_entry.address() as usize + address % PAE_LARGE_PAGE_SIZE);
+        }
+
+        let entry = self.table(directory, index)?[table_index];
+        if !entry.is_present() {
+            return None;
+        }
+
+        Some(entry.address() as usize + address % PAGE_SIZE)
+    }
+
+    fn flags(&self, page: Page) -> Option<EntryFlags> {
+        let (directory, index, table_index) = indices(page.start_address());
+        let directory_entry = self.directory(directory)[index];
+        let large = directory_entry.is_2m();
+        let entry = if large {
+            directory_entry
+        } else {
+            self.table(directory, index)?[table_index]
+        };
+        if !entry.is_present() {
+            return None;
+        }
+
+        let mut flags = EntryFlags::empty();
+        flags.set(
+            EntryFlags::PAT,
+            if large {
+                entry.is_large_pat()
+            } else {
+                entry.is_pat()
+            },
+        );
+        flags.set(EntryFlags::WRITABLE, entry.is_read_write());
+        flags.set(EntryFlags::USER, entry.is_user());
+        flags.set(EntryFlags::WRITE_THROUGH, entry.is_write_through());
+        flags.set(EntryFlags::CACHE_DISABLE, entry.cache_disabled());
+        flags.set(EntryFlags::GLOBAL, entry.is_global());
+        flags.set(EntryFlags::COW, entry.is_cow());
+        flags.set(EntryFlags::NO_EXECUTE, entry.is_no_execute());
+        Some(flags)
+    }
+
+    fn update_flags(&mut self, page: Page, flags: EntryFlags) {
+        let (directory, index, table_index) = indices(page.start_address());
+        if flags.contains(EntryFlags::USER) {
+            self.directory_mut(directory)[index].set_user(true);
+        }
+
+        let mut directory_entry = self.directory(directory)[index];
+        if directory_entry.is_present() && directory_entry.is_2m() {
+            self.set_entry_flags(&mut directory_entry, flags, true);
+            self.directory_mut(directory)[index] = directory_entry;
+
+            flush_tlb(page.start_address());
+            return;
+        }
+
+        let mut entry = self
+            .table(directory, index)
+            .map(|table| table[table_index])
+            .filter(|entry| entry.is_present())
+            .unwrap_or_else(|| panic!("Page {} is not mapped", page));
+        self.set_entry_flags(&mut entry, flags, false);
+
+        self.table_mut(directory, index).unwrap()[table_index] = entry;
+
+        flush_tlb(page.start_address());
+    }
+}
@@ -0,0 +1,38 @@
+use super::Frame;
+
+// Same limit as the frame allocators, frames above it are never handed out
+const MAX_FRAMES: usize = 1 << 20;
+
+// Owners beyond the first of every frame, so a frame nobody shares reads as 0. Only used by
+// address spaces, with the same interrupts-off locking. This is static rather than on the heap
+// because the #PF handler releases frames and must not touch the heap
+static mut SHARED: [u8; MAX_FRAMES] = [0; MAX_FRAMES];
+
+fn shared(frame: &Frame) -> &'static mut u8 {
+    assert!(frame.number < MAX_FRAMES);
+
+    unsafe { &mut SHARED[frame.number] }
+}
+
+// Another owner now maps `frame`
+pub fn share(frame: &Frame) {
+    let owners = shared(frame);
+    *owners = owners
+        .checked_add(1)
+        .unwrap_or_else(|| panic!("Too many owners of frame {}", frame));
+}
+
+pub fn is_shared(frame: &Frame) -> bool {
+    *shared(frame) > 0
+}
+
+// Drops an owner, returns true if that was the last one and the frame can be freed
+pub fn release(frame: &Frame) -> bool {
+    let owners = shared(frame);
+    if *owners > 0 {
+        *owners -= 1;
+        false
+    } else {
+        true
+    }
+}
@@ -0,0 +1,77 @@
+use core::ops::Range;
+
+use multiboot2::{BootInformation, ElfSectionFlags};
+
+use super::paging::{Directory, EntryFlags, Mapper, Mode};
+use super::{kernel_to_physical, physical_to_kernel, FrameAllocator};
+use crate::cpu::{gdt, smp};
+use crate::vga;
+
+const VGA_BUFFER_SIZE: usize = vga::BUFFER_WIDTH * vga::BUFFER_HEIGHT * 2;
+
+// Builds a directory that maps each allocated ELF section where it was linked with the
+// permissions its flags ask for, plus the VGA buffer and the multiboot information in the
+// higher half and `mmio` identity mapped, then switches to it. The boot directory's identity
+// mapping of low memory is gone afterwards
+pub fn remap_kernel(
+    boot_info: &BootInformation,
+    mode: Mode,
+    mmio: impl Iterator<Item = (Range<usize>, EntryFlags)>,
+    allocator: &mut impl FrameAllocator,
+) -> Directory {
+    let mut directory = Directory::new(mode, allocator);
+
+    let sections = boot_info
+        .elf_sections()
+        .expect("Multiboot information has no ELF sections");
+    for section in sections.filter(|section| section.is_allocated()) {
+        // Without NX, executable pages can only be told apart by not being writable
+        let mut flags = EntryFlags::empty();
+        flags.set(
+            EntryFlags::WRITABLE,
+            section.flags().contains(ElfSectionFlags::WRITABLE),
+        );
+        flags.set(
+            EntryFlags::NO_EXECUTE,
+            !section.flags().contains(ElfSectionFlags::EXECUTABLE),
+        );
+
+        let start = section.start_address() as usize;
+        directory.map_range(
+            start..section.end_address() as usize,
+            kernel_to_physical(start),
+            flags,
+            allocator,
+        );
+    }
+
+    let vga_buffer = physical_to_kernel(vga::BUFFER_ADDRESS);
+    directory.map_range(
+        vga_buffer..vga_buffer + VGA_BUFFER_SIZE,
+        vga::BUFFER_ADDRESS,
+        EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE,
+        allocator,
+    );
+    directory.map_range(
+        boot_info.start_address()..boot_info.end_address(),
+        kernel_to_physical(boot_info.start_address()),
+        EntryFlags::NO_EXECUTE,
+        allocator,
+    );
+
+    for (range, flags) in mmio {
+        directory.identity_map(range, flags, allocator);
+    }
+
+    // Address spaces created later copy the kernel's directory entries
+    directory.allocate_kernel_tables(allocator);
+
+    // The APs are up already, their double fault tasks need the new directory as well
+    let cr3 = directory.root_frame().start_address() as u32;
+    for cpu in smp::cpus() {
+        gdt::set_double_fault_cr3(cpu.index, cr3);
+    }
+    unsafe { directory.enable_paging() };
+
+    directory
+}
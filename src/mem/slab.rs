@@ -0,0 +1,298 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::ptr::{self, NonNull};
+
+use super::heap::Locked;
+use super::paging::{EntryFlags, Mapper, Page};
+use super::{FrameAllocator, PAGE_SIZE};
+use crate::misc::klog::{kdbg, kinfo};
+use crate::vga::{print, println};
+
+// Slabs get their own virtual window, each slab is aligned to its size so an object's slab
+// can be found by masking its address
+pub(super) const SLAB_START: usize = 0xE000_0000;
+pub(super) const SLAB_END: usize = 0xF000_0000;
+
+const MIN_OBJECTS_PER_SLAB: usize = 8;
+const MAX_SLAB_PAGES: usize = 64;
+
+static CACHES: Locked<Vec<&'static SlabCache>> = Locked::new(Vec::new());
+static NEXT_SLAB: Locked<usize> = Locked::new(SLAB_START);
+
+struct Slab {
+    cache: *const SlabCache,
+    next: *mut Slab,
+    free: *mut u8,
+    in_use: usize,
+}
+
+struct CacheState {
+    slabs: *mut Slab,
+    slab_count: usize,
+    in_use: usize,
+    allocations: u64,
+    frees: u64,
+}
+
+unsafe impl Send for CacheState {}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CacheStats {
+    pub slabs: usize,
+    pub objects: usize,
+    pub in_use: usize,
+    pub allocations: u64,
+    pub frees: u64,
+}
+
+pub struct SlabCache {
+    name: &'static str,
+    object_size: usize,
+    slab_size: usize,
+    // Offset of the first object, the slab header sits in front of it
+    first_object: usize,
+    objects_per_slab: usize,
+    // Where a free object keeps its free list link. Objects are only constructed once, so with a
+    // constructor the link goes behind the object's data instead of overwriting it
+    link_offset: usize,
+    // Runs once on every object when its slab is created, freed objects have to be handed back
+    // in the constructed state
+    constructor: Option<fn(*mut u8)>,
+    state: Locked<CacheState>,
+}
+
+impl SlabCache {
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn object_size(&self) -> usize {
+        self.object_size
+    }
+
+    fn link(&self, object: *mut u8) -> *mut *mut u8 {
+        (object as usize + self.link_offset) as *mut *mut u8
+    }
+
+    unsafe fn grow(&self, state: &mut CacheState) -> Option<*mut Slab> {
+        // The window only moves on once the slab is mapped, a slab that runs out of frames
+        // leaves it where it was
+        let base = NEXT_SLAB.lock(|next| {
+            let base = (*next + self.slab_size - 1) & !(self.slab_size - 1);
+            if base + self.slab_size > SLAB_END {
+                return None;
+            }
+
+            super::with_kernel_directory(|directory, allocator| {
+                for address in (base..base + self.slab_size).step_by(PAGE_SIZE) {
+                    let Some(frame) = allocator.allocate() else {
+                        // Hand back what's mapped so far
+                        for address in (base..address).step_by(PAGE_SIZE) {
+                            let frame =
+                                directory.unmap(Page::containing_address(address)).unwrap();
+                            allocator.deallocate(frame);
+                        }
+                        return None;
+                    };
+
+                    directory.map_to(
+                        Page::containing_address(address),
+                        frame,
+                        EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE,
+                        allocator,
+                    );
+                }
+
+                Some(())
+            })?;
+
+            *next = base + self.slab_size;
+            Some(base)
+        })?;
+
+        let first = base + self.first_object;
+        for i in 0..self.objects_per_slab {
+            let object = (first + i * self.object_size) as *mut u8;
+            if let Some(constructor) = self.constructor {
+                constructor(object);
+            }
+
+            let next = if i + 1 == self.objects_per_slab {
+                ptr::null_mut()
+            } else {
+                object.add(self.object_size)
+            };
+            self.link(object).write(next);
+        }
+
+        let slab = base as *mut Slab;
+        slab.write(Slab {
+            cache: self,
+            next: state.slabs,
+            free: first as *mut u8,
+            in_use: 0,
+        });
+
+        state.slabs = slab;
+        state.slab_count += 1;
+
+        Some(slab)
+    }
+
+    pub fn allocate(&self) -> Option<NonNull<u8>> {
+        let object = self.state.lock(|state| unsafe {
+            let mut slab = state.slabs;
+            while !slab.is_null() && (*slab).free.is_null() {
+                slab = (*slab).next;
+            }
+
+            if slab.is_null() {
+                slab = self.grow(state)?;
+            }
+
+            let object = (*slab).free;
+            (*slab).free = self.link(object).read();
+            (*slab).in_use += 1;
+
+            state.in_use += 1;
+            state.allocations += 1;
+
+            Some(object)
+        })?;
+
+        NonNull::new(object)
+    }
+
+    pub unsafe fn free(&self, object: NonNull<u8>) {
+        let address = object.as_ptr() as usize;
+        let slab = (address & !(self.slab_size - 1)) as *mut Slab;
+
+        assert!(
+            (SLAB_START..SLAB_END).contains(&address)
+                && ptr::eq((*slab).cache, self)
+                && address >= slab as usize + self.first_object
+                && (address - slab as usize - self.first_object) % self.object_size == 0,
+            "0x{:08X} is not an object of slab cache {}",
+            address,
+            self.name
+        );
+
+        self.state.lock(|state| {
+            let mut free = (*slab).free;
+            while !free.is_null() {
+                if free as usize == address {
+                    panic!(
+                        "Double free of 0x{:08X} in slab cache {}",
+                        address, self.name
+                    );
+                }
+                free = self.link(free).read();
+            }
+
+            let object = object.as_ptr();
+            self.link(object).write((*slab).free);
+            (*slab).free = object;
+            (*slab).in_use -= 1;
+
+            state.in_use -= 1;
+            state.frees += 1;
+        });
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.state.lock(|state| CacheStats {
+            slabs: state.slab_count,
+            objects: state.slab_count * self.objects_per_slab,
+            in_use: state.in_use,
+            allocations: state.allocations,
+            frees: state.frees,
+        })
+    }
+}
+
+pub fn create(
+    name: &'static str,
+    size: usize,
+    align: usize,
+    constructor: Option<fn(*mut u8)>,
+) -> &'static SlabCache {
+    assert!(
+        align.is_power_of_two(),
+        "Alignment {} is not a power of two",
+        align
+    );
+
+    // Free objects hold the free list link
+    let link_size = core::mem::size_of::<*mut u8>();
+    let align = align.max(core::mem::align_of::<*mut u8>());
+    let link_offset = match constructor {
+        Some(_) => (size + link_size - 1) & !(link_size - 1),
+        None => 0,
+    };
+    let object_size = (size.max(link_offset + link_size) + align - 1) & !(align - 1);
+    let first_object = (core::mem::size_of::<Slab>() + align - 1) & !(align - 1);
+
+    let pages = ((first_object + object_size * MIN_OBJECTS_PER_SLAB + PAGE_SIZE - 1) / PAGE_SIZE)
+        .next_power_of_two();
+    assert!(
+        pages <= MAX_SLAB_PAGES,
+        "Objects of {} bytes are too big for slab cache {}",
+        size,
+        name
+    );
+
+    let slab_size = pages * PAGE_SIZE;
+
+    // Looked up under the same lock as the push, so two caches can't both take the name
+    CACHES.lock(|caches| {
+        assert!(
+            caches.iter().all(|cache| cache.name != name),
+            "Slab cache {} already exists",
+            name
+        );
+
+        let cache: &'static SlabCache = Box::leak(Box::new(SlabCache {
+            name,
+            object_size,
+            slab_size,
+            first_object,
+            objects_per_slab: (slab_size - first_object) / object_size,
+            link_offset,
+            constructor,
+            state: Locked::new(CacheState {
+                slabs: ptr::null_mut(),
+                slab_count: 0,
+                in_use: 0,
+                allocations: 0,
+                frees: 0,
+            }),
+        }));
+
+        caches.push(cache);
+        cache
+    })
+}
+
+#[allow(dead_code)]
+pub fn find(name: &str) -> Option<&'static SlabCache> {
+    CACHES.lock(|caches| caches.iter().find(|cache| cache.name == name).copied())
+}
+
+pub fn dump() {
+    let caches = CACHES.lock(|caches| caches.clone());
+
+    kinfo!("Slab caches:");
+    for cache in caches {
+        let stats = cache.stats();
+        kdbg!(
+            "  {:<16} {:>5} B, {:>5}/{:<5} in use, {} slabs, {} allocations, {} frees",
+            cache.name(),
+            cache.object_size(),
+            stats.in_use,
+            stats.objects,
+            stats.slabs,
+            stats.allocations,
+            stats.frees
+        );
+    }
+}
@@ -0,0 +1,161 @@
+use core::ops::Range;
+
+use super::address_space::{AddressSpace, Backing, Region, RegionKind};
+use super::paging::{CacheType, EntryFlags, Mapper, Page};
+use super::{with_kernel_address_space, Frame, PAGE_SIZE};
+use crate::cpu::mtrr;
+use crate::misc::klog::{kdbg, kinfo};
+use crate::vga::{print, println};
+
+// Kernel virtual memory handed out on request, mappings made elsewhere stay out of it
+pub const WINDOW: Range<usize> = 0xF000_0000..0xFE00_0000;
+
+fn page_align_up(size: usize) -> usize {
+    (size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
+
+fn add(
+    address_space: &mut AddressSpace,
+    name: &'static str,
+    size: usize,
+    kind: RegionKind,
+    flags: EntryFlags,
+) -> Option<Range<usize>> {
+    let size = page_align_up(size);
+    let start = address_space.find_free(WINDOW, size, PAGE_SIZE)?;
+
+    address_space.add_region(Region::new(name, start..start + size, kind, flags));
+    Some(start..start + size)
+}
+
+// Reserves `size` bytes of zeroed memory, frames are only allocated once a page is touched
+pub fn allocate(name: &'static str, size: usize, flags: EntryFlags) -> Option<usize> {
+    with_kernel_address_space(|address_space, _| {
+        add(
+            address_space,
+            name,
+            size,
+            RegionKind::Mapped(Backing::Anonymous),
+            flags,
+        )
+        .map(|range| range.start)
+    })
+}
+
+// Maps a copy of `data` that is read in a page at a time as it is touched
+pub fn map_file(name: &'static str, data: &'static [u8], flags: EntryFlags) -> Option<usize> {
+    with_kernel_address_space(|address_space, _| {
+        add(
+            address_space,
+            name,
+            data.len().max(1),
+            RegionKind::Mapped(Backing::File { data, offset: 0 }),
+            flags,
+        )
+        .map(|range| range.start)
+    })
+}
+
+// Maps `length` bytes of physical memory starting at `physical` right away, returns the virtual
+// address of `physical`
+pub fn map_physical(
+    name: &'static str,
+    physical: usize,
+    length: usize,
+    flags: EntryFlags,
+) -> Option<usize> {
+    if length == 0 {
+        return None;
+    }
+
+    let offset = physical % PAGE_SIZE;
+    let start = physical - offset;
+
+    with_kernel_address_space(|address_space, allocator| {
+        let range = add(
+            address_space,
+            name,
+            offset + length,
+            RegionKind::Mapped(Backing::Physical { start }),
+            flags,
+        )?;
+
+        // Page by page, a large page couldn't be partially unmapped later
+        for (index, address) in range.clone().step_by(PAGE_SIZE).enumerate() {
+            let frame = Frame::containing_address(start + index * PAGE_SIZE);
+            address_space.directory_mut().map_to(
+                Page::containing_address(address),
+                frame,
+                flags,
+                allocator,
+            );
+        }
+
+        Some(range.start + offset)
+    })
+}
+
+// For drivers, maps device registers uncached
+pub fn map_mmio(physical: usize, length: usize) -> usize {
+    map_physical(
+        "mmio",
+        physical,
+        length,
+        (EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE).with_cache(CacheType::Uncacheable),
+    )
+    .unwrap_or_else(|| {
+        panic!(
+            "Out of virtual memory mapping {} bytes of MMIO at 0x{:08X}",
+            length, physical
+        )
+    })
+}
+
+// Writes to a framebuffer are combined into bursts instead of going out one by one, if the CPU
+// can do that
+pub fn map_framebuffer(physical: usize, length: usize) -> usize {
+    let cache = match mtrr::get() {
+        Some(mtrrs) if mtrrs.supports_write_combining() => CacheType::WriteCombining,
+        _ => CacheType::Uncacheable,
+    };
+
+    map_physical(
+        "framebuffer",
+        physical,
+        length,
+        (EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE).with_cache(cache),
+    )
+    .unwrap_or_else(|| {
+        panic!(
+            "Out of virtual memory mapping a {} byte framebuffer at 0x{:08X}",
+            length, physical
+        )
+    })
+}
+
+fn page_range(address: usize, length: usize) -> Range<usize> {
+    let start = address & !(PAGE_SIZE - 1);
+    start..page_align_up(address + length)
+}
+
+// Also works on parts of a region, what's left of it on either side stays
+pub fn unmap(address: usize, length: usize) {
+    with_kernel_address_space(|address_space, allocator| {
+        address_space.unmap(page_range(address, length), allocator)
+    });
+}
+
+pub fn protect(address: usize, length: usize, flags: EntryFlags) {
+    with_kernel_address_space(|address_space, _| {
+        address_space.protect(page_range(address, length), flags)
+    });
+}
+
+pub fn dump() {
+    kinfo!("Kernel virtual memory regions:");
+    with_kernel_address_space(|address_space, _| {
+        for region in address_space.regions() {
+            kdbg!("  {:?}", region);
+        }
+    });
+}
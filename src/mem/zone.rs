@@ -0,0 +1,167 @@
+use core::ops::Range;
+
+use multiboot2::{MemoryArea, MemoryAreaType};
+
+use super::PAGE_SIZE;
+
+// ISA DMA controllers only drive 24 address lines
+const DMA_END: u64 = 16 * 1024 * 1024;
+// What a kernel with a quarter of the address space could keep mapped at all times, the same
+// split Linux uses. Both boundaries are multiples of the largest buddy block
+const NORMAL_END: u64 = 896 * 1024 * 1024;
+
+const MAX_AREA_TYPES: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Zone {
+    Dma,
+    Normal,
+    High,
+}
+
+impl Zone {
+    pub const ALL: [Zone; 3] = [Zone::Dma, Zone::Normal, Zone::High];
+
+    // Order in which allocations that don't ask for a zone try them, the few DMA frames come last
+    pub const PREFERENCE: [Zone; 3] = [Zone::Normal, Zone::High, Zone::Dma];
+
+    pub fn containing_address(address: u64) -> Zone {
+        if address < DMA_END {
+            Zone::Dma
+        } else if address < NORMAL_END {
+            Zone::Normal
+        } else {
+            Zone::High
+        }
+    }
+
+    // Physical addresses in the zone
+    pub fn range(self) -> Range<u64> {
+        match self {
+            Zone::Dma => 0..DMA_END,
+            Zone::Normal => DMA_END..NORMAL_END,
+            Zone::High => NORMAL_END..u64::MAX,
+        }
+    }
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+// Frame counts, `managed` are the frames handed to the frame allocator
+#[derive(Clone, Copy, Default)]
+pub struct FrameStats {
+    pub total: usize,
+    pub managed: usize,
+    pub free: usize,
+}
+
+impl FrameStats {
+    // Never allocatable, firmware areas, low memory, the kernel image and the like
+    pub fn reserved(&self) -> usize {
+        self.total - self.managed
+    }
+
+    pub fn used(&self) -> usize {
+        self.managed - self.free
+    }
+}
+
+impl core::fmt::Debug for FrameStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{{ total: {} frames ({} KiB), free: {} frames, used: {} frames, reserved: {} frames }}",
+            self.total,
+            self.total * PAGE_SIZE / 1024,
+            self.free,
+            self.used(),
+            self.reserved()
+        )
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct AreaStats {
+    pub typ: MemoryAreaType,
+    pub areas: usize,
+    pub frames: FrameStats,
+}
+
+impl core::fmt::Debug for AreaStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{:?} ({} areas): {:?}",
+            self.typ, self.areas, self.frames
+        )
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Stats {
+    pub zones: [FrameStats; 3],
+    pub areas: [Option<AreaStats>; MAX_AREA_TYPES],
+}
+
+impl Stats {
+    pub fn zone(&self, zone: Zone) -> &FrameStats {
+        &self.zones[zone.index()]
+    }
+
+    pub fn area(&self, typ: MemoryAreaType) -> Option<&AreaStats> {
+        self.areas.iter().flatten().find(|area| area.typ == typ)
+    }
+}
+
+// Frame counts straight from the memory map, before anything is allocated
+static mut MEMORY_MAP: Option<Stats> = None;
+
+fn frames(range: Range<u64>) -> usize {
+    let page_size = PAGE_SIZE as u64;
+    if range.start >= range.end {
+        return 0;
+    }
+
+    // Frames that are only partially in the range count too
+    ((range.end + page_size - 1) / page_size - range.start / page_size) as usize
+}
+
+// Every area counts, not only the available ones, so ACPI and defective memory shows up as well
+pub fn init(areas: &[MemoryArea]) {
+    let mut stats = Stats {
+        zones: [FrameStats::default(); 3],
+        areas: [None; MAX_AREA_TYPES],
+    };
+
+    for area in areas {
+        let range = area.start_address()..area.start_address() + area.size();
+        let typ = MemoryAreaType::from(area.typ());
+
+        for zone in Zone::ALL {
+            let zone_range = zone.range();
+            let overlap = range.start.max(zone_range.start)..range.end.min(zone_range.end);
+            stats.zones[zone.index()].total += frames(overlap);
+        }
+
+        let slot = stats
+            .areas
+            .iter()
+            .position(|slot| slot.map_or(true, |slot| slot.typ == typ))
+            .unwrap_or_else(|| panic!("More than {} memory area types", MAX_AREA_TYPES));
+        let entry = stats.areas[slot].get_or_insert(AreaStats {
+            typ,
+            areas: 0,
+            frames: FrameStats::default(),
+        });
+        entry.areas += 1;
+        entry.frames.total += frames(range);
+    }
+
+    unsafe { MEMORY_MAP = Some(stats) };
+}
+
+pub fn memory_map() -> Option<Stats> {
+    unsafe { MEMORY_MAP }
+}
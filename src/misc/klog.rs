@@ -1,21 +1,44 @@
+use crate::time;
+use crate::vga::print;
+
+static mut SHOW_UPTIME: bool = false;
+
 macro_rules! kdbg {
-    ($fmt:expr) => {
+    ($fmt:expr) => {{
+        $crate::misc::klog::print_uptime();
         println!(concat!("[DBG]  ", $fmt));
-    };
-    ($fmt:expr, $($arg:tt)*) => {
+    }};
+    ($fmt:expr, $($arg:tt)*) => {{
+        $crate::misc::klog::print_uptime();
         println!(concat!("[DBG]  ", $fmt), $($arg)*);
-    };
+    }};
 }
 
 pub(crate) use kdbg;
 
 macro_rules! kinfo {
-    ($fmt:expr) => {
+    ($fmt:expr) => {{
+        $crate::misc::klog::print_uptime();
         println!(concat!("[INFO] ", $fmt));
-    };
-    ($fmt:expr, $($arg:tt)*) => {
+    }};
+    ($fmt:expr, $($arg:tt)*) => {{
+        $crate::misc::klog::print_uptime();
         println!(concat!("[INFO] ", $fmt), $($arg)*);
-    };
+    }};
 }
 
 pub(crate) use kinfo;
+
+pub fn set_show_uptime(show: bool) {
+    unsafe { SHOW_UPTIME = show };
+}
+
+#[doc(hidden)]
+pub fn print_uptime() {
+    if unsafe { !SHOW_UPTIME } {
+        return;
+    }
+
+    let uptime = time::uptime();
+    print!("[{:5}.{:06}] ", uptime.as_secs(), uptime.subsec_micros());
+}
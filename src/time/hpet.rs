@@ -0,0 +1,311 @@
+use crate::acpi::{self, GenericAddress};
+use crate::cpu;
+use crate::cpu::apic::{self, Polarity, TriggerMode, HPET_VECTOR};
+use crate::cpu::idt::{self, InterruptStackFrame};
+
+const REG_CAPABILITIES: usize = 0x000;
+const REG_CONFIGURATION: usize = 0x010;
+const REG_INTERRUPT_STATUS: usize = 0x020;
+const REG_MAIN_COUNTER: usize = 0x0F0;
+
+const CONFIGURATION_ENABLE: u64 = 1 << 0;
+const CONFIGURATION_LEGACY_REPLACEMENT: u64 = 1 << 1;
+
+const CAPABILITIES_64BIT: u64 = 1 << 13;
+const CAPABILITIES_LEGACY_REPLACEMENT: u64 = 1 << 15;
+
+const COMPARATOR_LEVEL_TRIGGERED: u64 = 1 << 1;
+const COMPARATOR_INTERRUPT_ENABLE: u64 = 1 << 2;
+const COMPARATOR_PERIODIC: u64 = 1 << 3;
+const COMPARATOR_PERIODIC_CAPABLE: u64 = 1 << 4;
+const COMPARATOR_VALUE_SET: u64 = 1 << 6;
+const COMPARATOR_32BIT: u64 = 1 << 8;
+const COMPARATOR_ROUTE_SHIFT: u64 = 9;
+const COMPARATOR_ROUTE_MASK: u64 = 0x1F << COMPARATOR_ROUTE_SHIFT;
+
+const FEMTOSECONDS_PER_NANOSECOND: u64 = 1_000_000;
+const FEMTOSECONDS_PER_SECOND: u64 = 1_000_000_000_000_000;
+
+static mut HPET: Option<Hpet> = None;
+// Comparator and GSI used for one-shots, and the callback of the pending one
+static mut ONESHOT_ROUTE: Option<(u8, u32)> = None;
+static mut ONESHOT: Option<fn()> = None;
+// Last main counter value, a 32-bit counter gets extended to 64 bits with it
+static mut COUNTER: u64 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Route {
+    // Comparator 0 replaces the PIT on IRQ 0, comparator 1 the RTC on IRQ 8
+    Legacy,
+    Gsi(u32),
+}
+
+pub struct Hpet {
+    base: usize,
+}
+
+impl Hpet {
+    pub unsafe fn new(base: usize) -> Hpet {
+        Hpet { base }
+    }
+
+    pub fn base(&self) -> usize {
+        self.base
+    }
+
+    unsafe fn read(&self, reg: usize) -> u64 {
+        let low = ((self.base + reg) as *const u32).read_volatile();
+        let high = ((self.base + reg + 4) as *const u32).read_volatile();
+
+        ((high as u64) << 32) | (low as u64)
+    }
+
+    unsafe fn write(&self, reg: usize, value: u64) {
+        ((self.base + reg) as *mut u32).write_volatile(value as u32);
+        ((self.base + reg + 4) as *mut u32).write_volatile((value >> 32) as u32);
+    }
+
+    fn comparator_config(n: u8) -> usize {
+        0x100 + 0x20 * n as usize
+    }
+
+    fn comparator_value(n: u8) -> usize {
+        0x108 + 0x20 * n as usize
+    }
+
+    pub fn period_fs(&self) -> u32 {
+        unsafe { (self.read(REG_CAPABILITIES) >> 32) as u32 }
+    }
+
+    pub fn frequency(&self) -> u64 {
+        FEMTOSECONDS_PER_SECOND / self.period_fs() as u64
+    }
+
+    pub fn comparator_count(&self) -> u8 {
+        unsafe { (((self.read(REG_CAPABILITIES) >> 8) & 0x1F) + 1) as u8 }
+    }
+
+    pub fn is_64bit(&self) -> bool {
+        unsafe { self.read(REG_CAPABILITIES) & CAPABILITIES_64BIT != 0 }
+    }
+
+    pub fn legacy_replacement_capable(&self) -> bool {
+        unsafe { self.read(REG_CAPABILITIES) & CAPABILITIES_LEGACY_REPLACEMENT != 0 }
+    }
+
+    // Extends a 32-bit counter in software, which only works if it's read at least once per
+    // wrap, about every 5 minutes at the usual 14.3 MHz. The tick handler takes care of that
+    pub fn counter(&self) -> u64 {
+        if self.is_64bit() {
+            // Re-read if the low half wrapped between the two 32-bit reads
+            loop {
+                let high =
+                    unsafe { ((self.base + REG_MAIN_COUNTER + 4) as *const u32).read_volatile() };
+                let value = unsafe { self.read(REG_MAIN_COUNTER) };
+
+                if (value >> 32) as u32 == high {
+                    return value;
+                }
+            }
+        }
+
+        cpu::without_interrupts(|| unsafe {
+            let low = ((self.base + REG_MAIN_COUNTER) as *const u32).read_volatile() as u64;
+
+            let mut value = (COUNTER & !(u32::MAX as u64)) | low;
+            if value < COUNTER {
+                value += 1 << 32;
+            }
+
+            COUNTER = value;
+            value
+        })
+    }
+
+    pub fn ticks_since(&self, start: u64) -> u64 {
+        self.counter() - start
+    }
+
+    pub fn ticks_to_ns(&self, ticks: u64) -> u64 {
+        let period = self.period_fs() as u64;
+
+        // Split the conversion so `ticks * period` can't overflow
+        (ticks / FEMTOSECONDS_PER_NANOSECOND) * period
+            + (ticks % FEMTOSECONDS_PER_NANOSECOND) * period / FEMTOSECONDS_PER_NANOSECOND
+    }
+
+    pub fn ns_to_ticks(&self, ns: u64) -> u64 {
+        let period = self.period_fs() as u64;
+
+        (ns / period) * FEMTOSECONDS_PER_NANOSECOND
+            + (ns % period) * FEMTOSECONDS_PER_NANOSECOND / period
+    }
+
+    pub fn supports_periodic(&self, n: u8) -> bool {
+        unsafe { self.read(Self::comparator_config(n)) & COMPARATOR_PERIODIC_CAPABLE != 0 }
+    }
+
+    // Bitmap of I/O APIC inputs the comparator can be routed to
+    pub fn route_capabilities(&self, n: u8) -> u32 {
+        unsafe { (self.read(Self::comparator_config(n)) >> 32) as u32 }
+    }
+
+    pub unsafe fn enable(&self, legacy_replacement: bool) {
+        let mut config = self.read(REG_CONFIGURATION) | CONFIGURATION_ENABLE;
+        if legacy_replacement {
+            config |= CONFIGURATION_LEGACY_REPLACEMENT;
+        } else {
+            config &= !CONFIGURATION_LEGACY_REPLACEMENT;
+        }
+
+        self.write(REG_CONFIGURATION, config);
+    }
+
+    unsafe fn configure(&self, n: u8, route: Route, periodic: bool) -> u64 {
+        assert!(n < self.comparator_count());
+
+        let mut config = self.read(Self::comparator_config(n));
+        config &= !(COMPARATOR_LEVEL_TRIGGERED
+            | COMPARATOR_PERIODIC
+            | COMPARATOR_32BIT
+            | COMPARATOR_ROUTE_MASK);
+        config |= COMPARATOR_INTERRUPT_ENABLE;
+
+        match route {
+            Route::Legacy => assert!(n < 2, "Only comparators 0 and 1 have legacy routes"),
+            Route::Gsi(gsi) => {
+                assert!(
+                    gsi < 32 && self.route_capabilities(n) & (1 << gsi) != 0,
+                    "HPET comparator {} can't be routed to GSI {}",
+                    n,
+                    gsi
+                );
+                config |= (gsi as u64) << COMPARATOR_ROUTE_SHIFT;
+            }
+        }
+
+        if periodic {
+            assert!(
+                self.supports_periodic(n),
+                "HPET comparator {} is not periodic capable",
+                n
+            );
+            config |= COMPARATOR_PERIODIC | COMPARATOR_VALUE_SET;
+        }
+
+        config
+    }
+
+    pub unsafe fn start_periodic(&self, n: u8, period_ticks: u64, route: Route) {
+        let config = self.configure(n, route, true);
+
+        // With VALUE_SET, the first write sets the comparator, the second the period
+        self.write(Self::comparator_config(n), config);
+        self.write(Self::comparator_value(n), self.counter() + period_ticks);
+        self.write(Self::comparator_value(n), period_ticks);
+    }
+
+    pub unsafe fn start_oneshot(&self, n: u8, delay_ticks: u64, route: Route) {
+        let config = self.configure(n, route, false);
+
+        self.write(Self::comparator_config(n), config);
+        self.write(Self::comparator_value(n), self.counter() + delay_ticks);
+    }
+
+    pub unsafe fn stop(&self, n: u8) {
+        let config = self.read(Self::comparator_config(n));
+        self.write(
+            Self::comparator_config(n),
+            config & !(COMPARATOR_INTERRUPT_ENABLE | COMPARATOR_PERIODIC),
+        );
+        self.write(REG_INTERRUPT_STATUS, 1 << n);
+    }
+}
+
+impl core::fmt::Debug for Hpet {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "Hpet {{ base: 0x{:08X}, period: {} fs, frequency: {} Hz, comparators: {}, 64bit: {}, legacy_replacement: {} }}",
+            self.base,
+            self.period_fs(),
+            self.frequency(),
+            self.comparator_count(),
+            self.is_64bit(),
+            self.legacy_replacement_capable()
+        )
+    }
+}
+
+pub fn init(table: &acpi::Hpet) -> Option<&'static Hpet> {
+    let base = table.base_address;
+    if base.address_space != GenericAddress::SPACE_SYSTEM_MEMORY || base.address() > u32::MAX as u64
+    {
+        return None;
+    }
+
+    unsafe {
+        let hpet = Hpet::new(base.address() as usize);
+        for n in 0..hpet.comparator_count() {
+            hpet.stop(n);
+        }
+        hpet.enable(false);
+
+        Some(HPET.insert(hpet))
+    }
+}
+
+pub fn get() -> Option<&'static Hpet> {
+    unsafe { HPET.as_ref() }
+}
+
+extern "x86-interrupt" fn oneshot(_frame: InterruptStackFrame) {
+    if let Some((n, _)) = unsafe { ONESHOT_ROUTE } {
+        unsafe { get().unwrap().stop(n) };
+    }
+
+    if let Some(callback) = unsafe { ONESHOT.take() } {
+        callback();
+    }
+
+    apic::end_of_interrupt();
+}
+
+// Routes a free comparator through the I/O APIC for one-shots. Comparators 0 and 1 are left
+// alone since legacy replacement takes them over, and GSIs below 16 belong to ISA IRQs
+pub fn enable_oneshot_timer() -> Option<(u8, u32)> {
+    assert!(apic::is_enabled(), "HPET one-shots need the I/O APIC");
+
+    let hpet = get()?;
+    let (n, gsi) = (2..hpet.comparator_count()).find_map(|n| {
+        let capabilities = hpet.route_capabilities(n);
+        (16..32)
+            .find(|gsi| capabilities & (1 << gsi) != 0)
+            .map(|gsi| (n, gsi))
+    })?;
+
+    idt::set_handler(HPET_VECTOR, oneshot);
+    apic::route_gsi(gsi, HPET_VECTOR, Polarity::ActiveHigh, TriggerMode::Edge);
+    unsafe { ONESHOT_ROUTE = Some((n, gsi)) };
+
+    Some((n, gsi))
+}
+
+pub fn set_oneshot(delay_ns: u64, callback: fn()) {
+    let (n, gsi) = unsafe { ONESHOT_ROUTE }.expect("HPET one-shot timer is not enabled");
+    let hpet = get().unwrap();
+
+    cpu::without_interrupts(|| unsafe {
+        ONESHOT = Some(callback);
+        hpet.start_oneshot(n, hpet.ns_to_ticks(delay_ns), Route::Gsi(gsi));
+    });
+}
+
+pub fn cancel_oneshot() {
+    let (n, _) = unsafe { ONESHOT_ROUTE }.expect("HPET one-shot timer is not enabled");
+
+    cpu::without_interrupts(|| unsafe {
+        get().unwrap().stop(n);
+        ONESHOT = None;
+    });
+}
@@ -0,0 +1,183 @@
+pub mod hpet;
+pub mod pit;
+pub mod rtc;
+pub mod tsc;
+
+use core::time::Duration;
+
+use crate::cpu::{self, irq};
+
+pub use rtc::DateTime;
+
+pub const DEFAULT_FREQUENCY: u32 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TickSource {
+    Pit,
+    Rtc,
+    Hpet,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Clocksource {
+    Ticks,
+    Tsc,
+    Hpet,
+}
+
+static mut TICKS: u64 = 0;
+static mut FREQUENCY: u32 = 0;
+static mut TICK_SOURCE: TickSource = TickSource::Pit;
+
+static mut CLOCKSOURCE: Clocksource = Clocksource::Ticks;
+// Clocksource reading and nanoseconds since boot at the time it was selected
+static mut CLOCKSOURCE_BASE: (u64, u64) = (0, 0);
+
+fn tick(line: u8) {
+    if line == rtc::IRQ_LINE {
+        unsafe { rtc::acknowledge_interrupt() };
+    }
+
+    if clocksource() == Clocksource::Hpet {
+        // Keeps the software extension of a 32-bit HPET counter from missing a wrap
+        hpet::get().unwrap().counter();
+    }
+
+    unsafe { TICKS += 1 };
+}
+
+pub fn is_running() -> bool {
+    unsafe { FREQUENCY != 0 }
+}
+
+pub fn tick_source() -> TickSource {
+    unsafe { TICK_SOURCE }
+}
+
+pub fn frequency() -> u32 {
+    unsafe { FREQUENCY }
+}
+
+pub fn ticks() -> u64 {
+    // A 64-bit read is not atomic on i686, don't let the IRQ tear it
+    cpu::without_interrupts(|| unsafe { TICKS })
+}
+
+pub fn uptime() -> Duration {
+    if !is_running() {
+        return Duration::ZERO;
+    }
+
+    let ticks = ticks();
+    let frequency = frequency() as u64;
+
+    Duration::new(
+        ticks / frequency,
+        ((ticks % frequency) * 1_000_000_000 / frequency) as u32,
+    )
+}
+
+pub fn clocksource() -> Clocksource {
+    unsafe { CLOCKSOURCE }
+}
+
+pub fn set_clocksource(clocksource: Clocksource) {
+    match clocksource {
+        Clocksource::Ticks => {}
+        Clocksource::Tsc => assert!(tsc::is_calibrated(), "TSC is not calibrated"),
+        Clocksource::Hpet => assert!(hpet::get().is_some(), "HPET is not initialized"),
+    }
+
+    cpu::without_interrupts(|| unsafe {
+        let now = now_ns();
+        let base = match clocksource {
+            Clocksource::Ticks => 0,
+            Clocksource::Tsc => tsc::read(),
+            Clocksource::Hpet => hpet::get().unwrap().counter(),
+        };
+
+        CLOCKSOURCE_BASE = (base, now);
+        CLOCKSOURCE = clocksource;
+    });
+}
+
+pub fn now_ns() -> u64 {
+    let (base, base_ns) = unsafe { CLOCKSOURCE_BASE };
+
+    match clocksource() {
+        Clocksource::Ticks => uptime().as_nanos() as u64,
+        Clocksource::Tsc => base_ns + tsc::cycles_to_ns(tsc::read() - base),
+        Clocksource::Hpet => {
+            let hpet = hpet::get().unwrap();
+            base_ns + hpet.ticks_to_ns(hpet.ticks_since(base))
+        }
+    }
+}
+
+pub fn wall_clock() -> DateTime {
+    rtc::read()
+}
+
+// Busy waits on the clocksource, for delays shorter than a tick
+pub fn delay_ns(ns: u64) {
+    let target = now_ns() + ns;
+    while now_ns() < target {
+        core::hint::spin_loop();
+    }
+}
+
+pub fn sleep_ticks(ticks: u64) {
+    assert!(is_running(), "Tick clock is not running");
+    assert!(
+        cpu::interrupts_enabled(),
+        "Sleeping with interrupts disabled"
+    );
+
+    let target = self::ticks() + ticks;
+    while self::ticks() < target {
+        cpu::halt();
+    }
+}
+
+pub fn init(source: TickSource, frequency: u32) {
+    let line = match source {
+        TickSource::Pit | TickSource::Hpet => 0,
+        TickSource::Rtc => rtc::IRQ_LINE,
+    };
+
+    cpu::without_interrupts(|| unsafe {
+        TICKS = 0;
+        TICK_SOURCE = source;
+
+        match source {
+            TickSource::Pit => {
+                let divisor = pit::divisor_for(frequency);
+
+                FREQUENCY = pit::actual_frequency(divisor);
+                pit::start_periodic(divisor);
+            }
+            TickSource::Rtc => {
+                let rate = rtc::rate_for(frequency);
+
+                FREQUENCY = rtc::actual_frequency(rate);
+                rtc::start_periodic(rate);
+            }
+            TickSource::Hpet => {
+                let hpet = hpet::get().expect("HPET is not initialized");
+                assert!(
+                    hpet.legacy_replacement_capable(),
+                    "HPET can't replace the PIT"
+                );
+
+                let period = hpet.frequency() / frequency as u64;
+
+                FREQUENCY = (hpet.frequency() / period) as u32;
+                hpet.enable(true);
+                hpet.start_periodic(0, period, hpet::Route::Legacy);
+            }
+        }
+    });
+
+    irq::register(line, tick)
+        .unwrap_or_else(|error| panic!("Can't register the tick on IRQ {}: {:?}", line, error));
+}
@@ -0,0 +1,43 @@
+use crate::cpu::port::outb;
+
+pub const BASE_FREQUENCY: u32 = 1_193_182;
+
+const CHANNEL0_DATA: u16 = 0x40;
+const COMMAND: u16 = 0x43;
+
+const COMMAND_CHANNEL0: u8 = 0b00 << 6;
+const ACCESS_LOBYTE_HIBYTE: u8 = 0b11 << 4;
+const MODE_RATE_GENERATOR: u8 = 0b010 << 1;
+
+pub fn divisor_for(frequency: u32) -> u16 {
+    assert!(frequency > 0);
+
+    let divisor = BASE_FREQUENCY / frequency;
+    assert!(
+        (1..=0x10000).contains(&divisor),
+        "PIT frequency {} Hz is out of range",
+        frequency
+    );
+
+    // A divisor of 0 is interpreted as 65536
+    (divisor & 0xFFFF) as u16
+}
+
+pub fn actual_frequency(divisor: u16) -> u32 {
+    let divisor = if divisor == 0 {
+        0x10000
+    } else {
+        divisor as u32
+    };
+
+    BASE_FREQUENCY / divisor
+}
+
+pub unsafe fn start_periodic(divisor: u16) {
+    outb(
+        COMMAND,
+        COMMAND_CHANNEL0 | ACCESS_LOBYTE_HIBYTE | MODE_RATE_GENERATOR,
+    );
+    outb(CHANNEL0_DATA, (divisor & 0xFF) as u8);
+    outb(CHANNEL0_DATA, (divisor >> 8) as u8);
+}
@@ -0,0 +1,174 @@
+use crate::cpu;
+use crate::cpu::port::{inb, outb};
+
+pub const IRQ_LINE: u8 = 8;
+pub const BASE_FREQUENCY: u32 = 32768;
+
+const CMOS_INDEX: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0A;
+const REG_STATUS_B: u8 = 0x0B;
+const REG_STATUS_C: u8 = 0x0C;
+
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 1 << 7;
+const STATUS_B_PERIODIC_INTERRUPT: u8 = 1 << 6;
+const STATUS_B_24_HOUR: u8 = 1 << 1;
+const STATUS_B_BINARY: u8 = 1 << 2;
+const HOURS_PM: u8 = 1 << 7;
+
+static mut CENTURY_REGISTER: Option<u8> = None;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl core::fmt::Display for DateTime {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct RawDateTime {
+    second: u8,
+    minute: u8,
+    hour: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+    century: u8,
+}
+
+// An interrupt between selecting the register and accessing it could select another one
+unsafe fn read_register(reg: u8) -> u8 {
+    cpu::without_interrupts(|| {
+        outb(CMOS_INDEX, reg);
+        inb(CMOS_DATA)
+    })
+}
+
+unsafe fn write_register(reg: u8, value: u8) {
+    cpu::without_interrupts(|| {
+        outb(CMOS_INDEX, reg);
+        outb(CMOS_DATA, value);
+    });
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value & 0x0F) + (value >> 4) * 10
+}
+
+unsafe fn read_raw() -> RawDateTime {
+    while read_register(REG_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0 {}
+
+    RawDateTime {
+        second: read_register(REG_SECONDS),
+        minute: read_register(REG_MINUTES),
+        hour: read_register(REG_HOURS),
+        day: read_register(REG_DAY),
+        month: read_register(REG_MONTH),
+        year: read_register(REG_YEAR),
+        century: match CENTURY_REGISTER {
+            Some(reg) => read_register(reg),
+            None => 0,
+        },
+    }
+}
+
+// The century register location is only known from the ACPI FADT
+pub fn set_century_register(reg: Option<u8>) {
+    unsafe { CENTURY_REGISTER = reg.filter(|&reg| reg != 0) };
+}
+
+pub fn read() -> DateTime {
+    let (raw, status_b) = unsafe {
+        // An update can still start while we read, so read until we get the same value twice
+        let mut raw = read_raw();
+        loop {
+            let again = read_raw();
+            if again == raw {
+                break;
+            }
+            raw = again;
+        }
+
+        (raw, read_register(REG_STATUS_B))
+    };
+
+    let binary = status_b & STATUS_B_BINARY != 0;
+    let decode = |value: u8| if binary { value } else { from_bcd(value) };
+
+    let pm = raw.hour & HOURS_PM != 0;
+    let mut hour = decode(raw.hour & !HOURS_PM);
+    if status_b & STATUS_B_24_HOUR == 0 {
+        hour = match (hour, pm) {
+            (12, false) => 0,
+            (12, true) => 12,
+            (hour, true) => hour + 12,
+            (hour, false) => hour,
+        };
+    }
+
+    let year = decode(raw.year) as u16;
+    let year = if unsafe { CENTURY_REGISTER.is_some() } {
+        decode(raw.century) as u16 * 100 + year
+    } else {
+        2000 + year
+    };
+
+    DateTime {
+        year,
+        month: decode(raw.month),
+        day: decode(raw.day),
+        hour,
+        minute: decode(raw.minute),
+        second: decode(raw.second),
+    }
+}
+
+// The supported rate closest to `frequency`, rates 1 and 2 are unusable
+pub fn rate_for(frequency: u32) -> u8 {
+    assert!(frequency > 0);
+
+    (3..=15)
+        .min_by_key(|&rate| actual_frequency(rate).abs_diff(frequency))
+        .unwrap()
+}
+
+pub fn actual_frequency(rate: u8) -> u32 {
+    BASE_FREQUENCY >> (rate - 1)
+}
+
+pub unsafe fn start_periodic(rate: u8) {
+    assert!((3..=15).contains(&rate));
+
+    let status_a = read_register(REG_STATUS_A);
+    write_register(REG_STATUS_A, (status_a & 0xF0) | rate);
+
+    let status_b = read_register(REG_STATUS_B);
+    write_register(REG_STATUS_B, status_b | STATUS_B_PERIODIC_INTERRUPT);
+
+    acknowledge_interrupt();
+}
+
+// Status register C has to be read after every RTC interrupt, otherwise no further interrupts fire
+pub unsafe fn acknowledge_interrupt() {
+    read_register(REG_STATUS_C);
+}
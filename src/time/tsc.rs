@@ -0,0 +1,106 @@
+use core::arch::asm;
+
+use crate::cpu;
+use crate::cpu::apic::{self, TIMER_VECTOR};
+use crate::cpu::cpuid::TscInfo;
+use crate::cpu::idt::{self, InterruptStackFrame};
+
+const CALIBRATION_TICKS: u64 = 50;
+
+static mut FREQUENCY: u64 = 0;
+static mut DEADLINE_TIMER: bool = false;
+static mut ONESHOT: Option<fn()> = None;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Calibration {
+    Cpuid,
+    // Measured against whichever timer drives the tick
+    TickSource(super::TickSource),
+}
+
+pub fn read() -> u64 {
+    let (high, low): (u32, u32);
+    unsafe {
+        asm!("rdtsc", out("eax") low, out("edx") high, options(nomem, nostack, preserves_flags))
+    };
+    ((high as u64) << 32) | (low as u64)
+}
+
+pub fn frequency() -> u64 {
+    unsafe { FREQUENCY }
+}
+
+pub fn is_calibrated() -> bool {
+    frequency() != 0
+}
+
+pub fn calibrate(info: Option<&TscInfo>) -> Calibration {
+    if let Some(frequency) = info.and_then(TscInfo::tsc_frequency) {
+        unsafe { FREQUENCY = frequency };
+        return Calibration::Cpuid;
+    }
+
+    // Start right after a tick so the measured window covers whole tick periods
+    super::sleep_ticks(1);
+    let start = read();
+    super::sleep_ticks(CALIBRATION_TICKS);
+    let end = read();
+
+    unsafe { FREQUENCY = (end - start) * super::frequency() as u64 / CALIBRATION_TICKS };
+    Calibration::TickSource(super::tick_source())
+}
+
+pub fn cycles_to_ns(cycles: u64) -> u64 {
+    let frequency = frequency();
+
+    // Split the conversion so `cycles * 10^9` can't overflow
+    (cycles / frequency) * 1_000_000_000 + (cycles % frequency) * 1_000_000_000 / frequency
+}
+
+pub fn ns_to_cycles(ns: u64) -> u64 {
+    let frequency = frequency();
+
+    (ns / 1_000_000_000) * frequency + (ns % 1_000_000_000) * frequency / 1_000_000_000
+}
+
+extern "x86-interrupt" fn deadline(_frame: InterruptStackFrame) {
+    if let Some(callback) = unsafe { ONESHOT.take() } {
+        callback();
+    }
+
+    apic::end_of_interrupt();
+}
+
+pub fn has_deadline_timer() -> bool {
+    unsafe { DEADLINE_TIMER }
+}
+
+pub fn enable_deadline_timer() {
+    assert!(is_calibrated(), "TSC is not calibrated");
+    assert!(
+        apic::is_enabled(),
+        "TSC-deadline timers need the local APIC"
+    );
+
+    idt::set_handler(TIMER_VECTOR, deadline);
+    unsafe {
+        apic::local().enable_tsc_deadline(TIMER_VECTOR);
+        DEADLINE_TIMER = true;
+    }
+}
+
+pub fn set_oneshot(delay_ns: u64, callback: fn()) {
+    assert!(has_deadline_timer(), "TSC-deadline timer is not enabled");
+
+    cpu::without_interrupts(|| unsafe {
+        ONESHOT = Some(callback);
+        apic::local().set_tsc_deadline(read() + ns_to_cycles(delay_ns));
+    });
+}
+
+pub fn cancel_oneshot() {
+    cpu::without_interrupts(|| unsafe {
+        apic::local().set_tsc_deadline(0);
+        ONESHOT = None;
+    });
+}
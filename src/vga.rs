@@ -119,6 +119,7 @@ impl VgaBufferChar {
     }
 }
 
+pub const BUFFER_ADDRESS: usize = 0xb8000;
 pub const BUFFER_WIDTH: usize = 80;
 pub const BUFFER_HEIGHT: usize = 25;
 const EMPTY_LINE: [VgaBufferChar; BUFFER_WIDTH] =
@@ -140,7 +141,7 @@ impl VgaBufferWriter {
             pos_y: 0,
             foreground: Color::White,
             background: Color::Black,
-            vga_buffer: 0xb8000 as *mut VgaBufferChar,
+            vga_buffer: crate::mem::physical_to_kernel(BUFFER_ADDRESS) as *mut VgaBufferChar,
             pending_newline: false,
         }
     }